@@ -0,0 +1,657 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result, anyhow};
+use clap::ValueEnum;
+use flacenc::component::BitRepr;
+use flacenc::error::Verify;
+use rubato::audioadapter_buffers::direct::InterleavedSlice;
+use rubato::{Fft, FixedSync, Resampler};
+use symphonia::core::audio::Channels;
+use symphonia::core::codecs::audio::AudioDecoderOptions;
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::probe::Hint;
+use symphonia::core::formats::{FormatOptions, TrackType};
+use symphonia::core::io::{MediaSourceStream, MediaSourceStreamOptions};
+use symphonia::core::meta::MetadataOptions;
+
+/// Number of frames the resampler processes at a time.
+const RESAMPLER_CHUNK_SIZE: usize = 1024;
+
+/// Audio file format for rendered and converted output files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum AudioFormat {
+    #[default]
+    Wav,
+    Flac,
+}
+
+impl AudioFormat {
+    /// File extension without the leading dot.
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::Wav => "wav",
+            Self::Flac => "flac",
+        }
+    }
+}
+
+/// Length of leading and trailing silence in seconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Silence {
+    pub leading: f64,
+    pub trailing: f64,
+}
+
+/// Integer bit depth for rendered output files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum BitDepth {
+    #[default]
+    #[value(name = "16")]
+    Bits16,
+    #[value(name = "24")]
+    Bits24,
+}
+
+impl BitDepth {
+    /// Number of bits per sample.
+    pub const fn bits(self) -> u16 {
+        match self {
+            Self::Bits16 => 16,
+            Self::Bits24 => 24,
+        }
+    }
+}
+
+/// Result of decoding a whole file.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DecodeCheck {
+    /// Duration of the decoded audio in seconds
+    pub duration: f64,
+    /// Number of packets that failed to decode
+    pub decode_errors: usize,
+}
+
+/// Decoded audio and the decode result.
+struct Decoded {
+    audio: AudioBuffer,
+    check: DecodeCheck,
+}
+
+/// Decoded audio as interleaved 32-bit float samples.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioBuffer {
+    pub sample_rate: u32,
+    pub channels: usize,
+    pub samples: Vec<f32>,
+}
+
+impl AudioBuffer {
+    /// Create an empty buffer with the given format.
+    pub const fn empty(sample_rate: u32, channels: usize) -> Self {
+        Self {
+            sample_rate,
+            channels,
+            samples: Vec::new(),
+        }
+    }
+
+    /// Number of sample frames, meaning one sample for each channel.
+    pub fn frames(&self) -> usize {
+        self.samples.len().checked_div(self.channels).unwrap_or(0)
+    }
+
+    /// Length in seconds.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn duration(&self) -> f64 {
+        self.frames() as f64 / f64::from(self.sample_rate)
+    }
+
+    /// Convert a time in seconds to the nearest frame index.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn frame_at(&self, seconds: f64) -> usize {
+        (seconds.max(0.0) * f64::from(self.sample_rate)).round() as usize
+    }
+
+    /// Return the part starting at `start` seconds and lasting at most `length` seconds.
+    ///
+    /// The excerpt is shorter if the track ends before `start + length`.
+    pub fn excerpt(&self, start: f64, length: f64) -> Result<Self> {
+        let start_frame = self.frame_at(start);
+        if start_frame >= self.frames() {
+            anyhow::bail!(
+                "Excerpt start {start:.1}s is past the end of the track ({:.1}s)",
+                self.duration()
+            );
+        }
+        let end_frame = (start_frame + self.frame_at(length)).min(self.frames());
+        Ok(Self {
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+            samples: self.samples[start_frame * self.channels..end_frame * self.channels].to_vec(),
+        })
+    }
+
+    /// Apply fade-in and fade-out with a quarter-sine gain curve.
+    ///
+    /// The fades are shortened to half of the length each if the audio is too short for them.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn apply_fades(&mut self, fade_in: f64, fade_out: f64) {
+        let frames = self.frames();
+        let fade_in_frames = self.frame_at(fade_in).min(frames / 2);
+        let fade_out_frames = self.frame_at(fade_out).min(frames / 2);
+
+        for frame in 0..fade_in_frames {
+            let gain = (std::f64::consts::FRAC_PI_2 * frame as f64 / fade_in_frames as f64).sin();
+            self.scale_frame(frame, gain);
+        }
+        for frame in 0..fade_out_frames {
+            let gain = (std::f64::consts::FRAC_PI_2 * frame as f64 / fade_out_frames as f64).sin();
+            self.scale_frame(frames - 1 - frame, gain);
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn scale_frame(&mut self, frame: usize, gain: f64) {
+        let start = frame * self.channels;
+        for sample in &mut self.samples[start..start + self.channels] {
+            *sample *= gain as f32;
+        }
+    }
+
+    /// Detect leading and trailing silence where all channels stay below the threshold in dBFS.
+    ///
+    /// Silence shorter than `min_duration` seconds is not counted.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn detect_silence(&self, threshold_db: f64, min_duration: f64) -> Silence {
+        let threshold = 10.0_f64.powf(threshold_db / 20.0) as f32;
+        let is_silent = |frame: &[f32]| frame.iter().all(|sample| sample.abs() < threshold);
+        let frames: Vec<&[f32]> = self.samples.chunks_exact(self.channels.max(1)).collect();
+        let leading_frames = frames.iter().take_while(|frame| is_silent(frame)).count();
+        let trailing_frames = if leading_frames == frames.len() {
+            0
+        } else {
+            frames.iter().rev().take_while(|frame| is_silent(frame)).count()
+        };
+        let min_frames = self.frame_at(min_duration);
+        let seconds = |count: usize| {
+            if count >= min_frames && count > 0 {
+                self.seconds_at(count)
+            } else {
+                0.0
+            }
+        };
+        Silence {
+            leading: seconds(leading_frames),
+            trailing: seconds(trailing_frames),
+        }
+    }
+
+    /// Remove the given leading and trailing silence.
+    pub fn trim(&self, silence: Silence) -> Self {
+        let start = self.frame_at(silence.leading).min(self.frames());
+        let end = self.frames().saturating_sub(self.frame_at(silence.trailing)).max(start);
+        Self {
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+            samples: self.samples[start * self.channels..end * self.channels].to_vec(),
+        }
+    }
+
+    /// Convert a frame index to a time in seconds.
+    #[allow(clippy::cast_precision_loss)]
+    fn seconds_at(&self, frame: usize) -> f64 {
+        frame as f64 / f64::from(self.sample_rate)
+    }
+
+    /// Append silence of the given length in seconds.
+    pub fn append_silence(&mut self, seconds: f64) {
+        let frames = self.frame_at(seconds);
+        self.samples.resize(self.samples.len() + frames * self.channels, 0.0);
+    }
+
+    /// Append audio with the same sample rate and channel count.
+    pub fn append(&mut self, other: &Self) -> Result<()> {
+        if other.sample_rate != self.sample_rate || other.channels != self.channels {
+            anyhow::bail!(
+                "Audio format mismatch: {} Hz {} channels, expected {} Hz {} channels",
+                other.sample_rate,
+                other.channels,
+                self.sample_rate,
+                self.channels
+            );
+        }
+        self.samples.extend_from_slice(&other.samples);
+        Ok(())
+    }
+
+    /// Append audio with the same format, overlapping the end with an equal-power crossfade.
+    ///
+    /// The crossfade is shortened if either part is shorter than the crossfade.
+    /// Returns the start position of the appended audio in seconds.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn append_crossfaded(&mut self, other: &Self, seconds: f64) -> Result<f64> {
+        let overlap = self.frame_at(seconds).min(self.frames()).min(other.frames());
+        let start_frame = self.frames() - overlap;
+        let start = start_frame as f64 / f64::from(self.sample_rate);
+        if overlap == 0 {
+            self.append(other)?;
+            return Ok(start);
+        }
+        let mut faded = other.clone();
+        for frame in 0..overlap {
+            let position = std::f64::consts::FRAC_PI_2 * (frame as f64 + 0.5) / overlap as f64;
+            self.scale_frame(start_frame + frame, position.cos());
+            faded.scale_frame(frame, position.sin());
+        }
+        let overlap_samples = overlap * self.channels;
+        let mixed_start = start_frame * self.channels;
+        for (sample, added) in self.samples[mixed_start..]
+            .iter_mut()
+            .zip(&faded.samples[..overlap_samples])
+        {
+            *sample += added;
+        }
+        self.append(&Self {
+            samples: faded.samples[overlap_samples..].to_vec(),
+            ..faded
+        })?;
+        Ok(start)
+    }
+
+    /// Root mean square level of all samples.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn rms(&self) -> f64 {
+        if self.samples.is_empty() {
+            return 0.0;
+        }
+        let sum: f64 = self.samples.iter().map(|&sample| f64::from(sample).powi(2)).sum();
+        (sum / self.samples.len() as f64).sqrt()
+    }
+
+    /// Highest absolute sample value.
+    pub fn peak(&self) -> f64 {
+        self.samples
+            .iter()
+            .map(|sample| f64::from(sample.abs()))
+            .fold(0.0, f64::max)
+    }
+
+    /// Multiply all samples with the given linear gain.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn apply_gain(&mut self, gain: f64) {
+        for sample in &mut self.samples {
+            *sample *= gain as f32;
+        }
+    }
+
+    /// Convert to the given sample rate and channel count.
+    pub fn convert(self, sample_rate: u32, channels: usize) -> Result<Self> {
+        self.remix(channels).resample(sample_rate)
+    }
+
+    /// Change the channel count.
+    ///
+    /// Mixing down to mono averages all channels and mono is copied to every channel.
+    /// Otherwise the first channels are kept and missing channels repeat the last one.
+    #[allow(clippy::cast_precision_loss)]
+    fn remix(self, channels: usize) -> Self {
+        if channels == self.channels {
+            return self;
+        }
+        let mut samples = Vec::with_capacity(self.frames() * channels);
+        for frame in self.samples.chunks_exact(self.channels) {
+            if channels == 1 {
+                samples.push(frame.iter().sum::<f32>() / self.channels as f32);
+            } else {
+                samples.extend((0..channels).map(|channel| frame[channel.min(self.channels - 1)]));
+            }
+        }
+        Self {
+            sample_rate: self.sample_rate,
+            channels,
+            samples,
+        }
+    }
+
+    /// Change the sample rate with a synchronous FFT resampler.
+    fn resample(self, sample_rate: u32) -> Result<Self> {
+        if sample_rate == self.sample_rate || self.samples.is_empty() {
+            return Ok(Self { sample_rate, ..self });
+        }
+        let mut resampler = Fft::<f32>::new(
+            self.sample_rate as usize,
+            sample_rate as usize,
+            RESAMPLER_CHUNK_SIZE,
+            self.channels,
+            FixedSync::Both,
+        )
+        .context("Failed to create resampler")?;
+        let input = InterleavedSlice::new(&self.samples, self.channels, self.frames())
+            .map_err(|error| anyhow!("Invalid audio buffer: {error:?}"))?;
+        let output = resampler
+            .process_all(&input, self.frames(), None)
+            .context("Failed to resample audio")?;
+        Ok(Self {
+            sample_rate,
+            channels: self.channels,
+            samples: output.take_data(),
+        })
+    }
+
+    /// Convert samples to integers with the given bit depth, clipping values outside the valid range.
+    #[allow(clippy::cast_possible_truncation)]
+    fn to_integer_samples(&self, bit_depth: u16) -> Vec<i32> {
+        let max_value = f64::from((1_i32 << (bit_depth - 1)) - 1);
+        self.samples
+            .iter()
+            .map(|&sample| {
+                (f64::from(sample) * max_value)
+                    .round()
+                    .clamp(-max_value - 1.0, max_value) as i32
+            })
+            .collect()
+    }
+}
+
+/// Decode the whole audio file into memory.
+///
+/// Packets that fail to decode are skipped.
+pub fn decode_file(path: &Path) -> Result<AudioBuffer> {
+    decode(path, true).map(|decoded| decoded.audio)
+}
+
+/// Decode the whole audio file, keeping the samples and the decode result.
+pub fn decode_file_checked(path: &Path) -> Result<(AudioBuffer, DecodeCheck)> {
+    decode(path, true).map(|decoded| (decoded.audio, decoded.check))
+}
+
+/// Decode the whole audio file without keeping the samples to find decode errors.
+pub fn verify_file(path: &Path) -> Result<DecodeCheck> {
+    decode(path, false).map(|decoded| decoded.check)
+}
+
+/// Decode the file, optionally keeping the decoded samples.
+fn decode(path: &Path, keep_samples: bool) -> Result<Decoded> {
+    let file = fs::File::open(path).context(format!("Failed to open audio file: {}", path.display()))?;
+    let stream = MediaSourceStream::new(Box::new(file), MediaSourceStreamOptions::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|ext| ext.to_str()) {
+        hint.with_extension(extension);
+    }
+
+    let mut format = symphonia::default::get_probe()
+        .probe(&hint, stream, FormatOptions::default(), MetadataOptions::default())
+        .context(format!("Unsupported audio format: {}", path.display()))?;
+    let track = format
+        .default_track(TrackType::Audio)
+        .ok_or_else(|| anyhow!("No audio track found: {}", path.display()))?;
+    let track_id = track.id;
+    let codec_params = track
+        .codec_params
+        .as_ref()
+        .and_then(|params| params.audio())
+        .cloned()
+        .ok_or_else(|| anyhow!("Missing audio codec parameters: {}", path.display()))?;
+
+    let mut decoder = symphonia::default::get_codecs()
+        .make_audio_decoder(&codec_params, &AudioDecoderOptions::default())
+        .context(format!("Unsupported audio codec: {}", path.display()))?;
+
+    let mut sample_rate = codec_params.sample_rate.unwrap_or_default();
+    let mut channels = codec_params.channels.as_ref().map_or(0, Channels::count);
+    let mut samples: Vec<f32> = Vec::new();
+    let mut packet_samples: Vec<f32> = Vec::new();
+    let mut frames = 0;
+    let mut decode_errors = 0;
+    loop {
+        let packet = match format.next_packet() {
+            Ok(Some(packet)) => packet,
+            Ok(None) | Err(SymphoniaError::ResetRequired) => break,
+            Err(error) => return Err(error).context(format!("Failed to read audio file: {}", path.display())),
+        };
+        if packet.track_id != track_id {
+            continue;
+        }
+        match decoder.decode(&packet) {
+            Ok(buffer) => {
+                sample_rate = buffer.spec().rate();
+                channels = buffer.spec().channels().count();
+                frames += buffer.frames();
+                if keep_samples {
+                    packet_samples.resize(buffer.samples_interleaved(), 0.0);
+                    buffer.copy_to_slice_interleaved(&mut packet_samples);
+                    samples.extend_from_slice(&packet_samples);
+                }
+            }
+            Err(SymphoniaError::DecodeError(_) | SymphoniaError::IoError(_)) => decode_errors += 1,
+            Err(error) => return Err(error).context(format!("Failed to decode audio file: {}", path.display())),
+        }
+    }
+
+    if sample_rate == 0 || channels == 0 {
+        anyhow::bail!("Failed to determine audio format: {}", path.display());
+    }
+    #[allow(clippy::cast_precision_loss)]
+    let duration = frames as f64 / f64::from(sample_rate);
+    Ok(Decoded {
+        audio: AudioBuffer {
+            sample_rate,
+            channels,
+            samples,
+        },
+        check: DecodeCheck {
+            duration,
+            decode_errors,
+        },
+    })
+}
+
+/// Write audio to the given path in the given format.
+pub fn write_audio_file(path: &Path, audio: &AudioBuffer, format: AudioFormat, bit_depth: u16) -> Result<()> {
+    match format {
+        AudioFormat::Wav => write_wav(path, audio, bit_depth),
+        AudioFormat::Flac => write_flac(path, audio, bit_depth),
+    }
+    .context(format!("Failed to write audio file: {}", path.display()))
+}
+
+fn write_wav(path: &Path, audio: &AudioBuffer, bit_depth: u16) -> Result<()> {
+    let spec = hound::WavSpec {
+        channels: u16::try_from(audio.channels)?,
+        sample_rate: audio.sample_rate,
+        bits_per_sample: bit_depth,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(path, spec)?;
+    for sample in audio.to_integer_samples(bit_depth) {
+        writer.write_sample(sample)?;
+    }
+    writer.finalize()?;
+    Ok(())
+}
+
+fn write_flac(path: &Path, audio: &AudioBuffer, bit_depth: u16) -> Result<()> {
+    let config = flacenc::config::Encoder::default()
+        .into_verified()
+        .map_err(|(_, error)| anyhow!("Invalid FLAC encoder config: {error:?}"))?;
+    let samples = audio.to_integer_samples(bit_depth);
+    let source = flacenc::source::MemSource::from_samples(
+        &samples,
+        audio.channels,
+        usize::from(bit_depth),
+        audio.sample_rate as usize,
+    );
+    let mut stream = flacenc::encode_with_fixed_block_size(&config, source, config.block_size)
+        .map_err(|error| anyhow!("Failed to encode FLAC: {error:?}"))?;
+    // The minimum block size must not count the shorter last block,
+    // otherwise decoders treat the stream as variable block size.
+    stream
+        .stream_info_mut()
+        .set_block_sizes(config.block_size, config.block_size)
+        .map_err(|error| anyhow!("Invalid FLAC block size: {error:?}"))?;
+    let mut sink = flacenc::bitsink::ByteSink::new();
+    stream
+        .write(&mut sink)
+        .map_err(|error| anyhow!("Failed to encode FLAC: {error:?}"))?;
+    fs::write(path, sink.as_slice())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod audio_tests {
+    use super::*;
+
+    fn constant_audio(seconds: usize) -> AudioBuffer {
+        AudioBuffer {
+            sample_rate: 100,
+            channels: 2,
+            samples: vec![0.5; seconds * 100 * 2],
+        }
+    }
+
+    #[test]
+    fn cuts_excerpt_from_start_offset() {
+        let audio = constant_audio(10);
+        let excerpt = audio.excerpt(2.0, 3.0).expect("should cut excerpt");
+        assert_eq!(excerpt.frames(), 300);
+        assert!((excerpt.duration() - 3.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn shortens_excerpt_at_end_of_track() {
+        let audio = constant_audio(10);
+        let excerpt = audio.excerpt(8.0, 5.0).expect("should cut excerpt");
+        assert_eq!(excerpt.frames(), 200);
+    }
+
+    #[test]
+    fn rejects_excerpt_start_past_end() {
+        let audio = constant_audio(10);
+        assert!(audio.excerpt(10.0, 5.0).is_err());
+    }
+
+    #[test]
+    fn fades_start_and_end_to_silence() {
+        let mut audio = constant_audio(10);
+        audio.apply_fades(1.0, 2.0);
+        assert!(audio.samples[0].abs() < f32::EPSILON);
+        assert!(audio.samples[audio.samples.len() - 1].abs() < 0.01);
+        assert!((audio.samples[audio.samples.len() / 2] - 0.5).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn converts_channels_and_sample_rate() {
+        let mono = AudioBuffer {
+            sample_rate: 22050,
+            channels: 1,
+            samples: vec![0.25; 22050],
+        };
+        let converted = mono.convert(44100, 2).expect("should convert audio");
+        assert_eq!(converted.sample_rate, 44100);
+        assert_eq!(converted.channels, 2);
+        assert_eq!(converted.frames(), 44100);
+
+        let stereo = AudioBuffer {
+            sample_rate: 100,
+            channels: 2,
+            samples: vec![0.2, 0.4, 0.2, 0.4],
+        };
+        let converted = stereo.convert(100, 1).expect("should convert audio");
+        assert_eq!(converted.samples.len(), 2);
+        assert!((converted.samples[0] - 0.3).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn appends_audio_with_silence_gap() {
+        let mut combined = AudioBuffer::empty(100, 2);
+        combined.append(&constant_audio(1)).expect("should append audio");
+        combined.append_silence(0.5);
+        combined.append(&constant_audio(2)).expect("should append audio");
+        assert_eq!(combined.frames(), 350);
+        assert!(combined.samples[200..300].iter().all(|sample| *sample == 0.0));
+
+        let mono = AudioBuffer::empty(100, 1);
+        assert!(combined.append(&mono).is_err());
+    }
+
+    #[test]
+    fn crossfades_with_constant_power() {
+        let mut combined = constant_audio(2);
+        let start = combined
+            .append_crossfaded(&constant_audio(2), 1.0)
+            .expect("should append audio");
+        assert!((start - 1.0).abs() < f64::EPSILON);
+        assert_eq!(combined.frames(), 300);
+        // Equal-power gains sum to more than one in the middle of the crossfade
+        let middle = combined.samples[150 * 2];
+        assert!((f64::from(middle) - std::f64::consts::FRAC_1_SQRT_2).abs() < 0.01);
+        assert!((combined.samples[299 * 2] - 0.5).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn shortens_crossfade_for_short_audio() {
+        let mut combined = constant_audio(1);
+        let start = combined
+            .append_crossfaded(&constant_audio(3), 2.0)
+            .expect("should append audio");
+        assert!(start.abs() < f64::EPSILON);
+        assert_eq!(combined.frames(), 300);
+    }
+
+    #[test]
+    fn detects_and_trims_leading_and_trailing_silence() {
+        let mut samples = vec![0.0; 100 * 2];
+        samples.extend(vec![0.5; 300 * 2]);
+        samples.extend(vec![0.001; 50 * 2]);
+        let audio = AudioBuffer {
+            sample_rate: 100,
+            channels: 2,
+            samples,
+        };
+        let silence = audio.detect_silence(-50.0, 0.2);
+        assert!((silence.leading - 1.0).abs() < f64::EPSILON);
+        assert!((silence.trailing - 0.5).abs() < f64::EPSILON);
+        let trimmed = audio.trim(silence);
+        assert_eq!(trimmed.frames(), 300);
+        assert!(
+            trimmed
+                .samples
+                .iter()
+                .all(|sample| (*sample - 0.5).abs() < f32::EPSILON)
+        );
+    }
+
+    #[test]
+    fn ignores_silence_shorter_than_minimum() {
+        let mut samples = vec![0.0; 10];
+        samples.extend(vec![0.5; 200]);
+        let audio = AudioBuffer {
+            sample_rate: 100,
+            channels: 1,
+            samples,
+        };
+        assert_eq!(audio.detect_silence(-50.0, 0.5), Silence::default());
+    }
+
+    #[test]
+    fn writes_and_decodes_wav_and_flac() {
+        let dir = tempfile::tempdir().expect("should create temp dir");
+        #[allow(clippy::cast_precision_loss)]
+        let audio = AudioBuffer {
+            sample_rate: 44100,
+            channels: 2,
+            samples: (0..44100 * 2).map(|i| ((i / 2) as f32 * 0.01).sin() * 0.5).collect(),
+        };
+        for format in [AudioFormat::Wav, AudioFormat::Flac] {
+            let path = dir.path().join(format!("test.{}", format.extension()));
+            for bit_depth in [BitDepth::Bits16, BitDepth::Bits24] {
+                write_audio_file(&path, &audio, format, bit_depth.bits()).expect("should write audio");
+                let decoded = decode_file(&path).expect("should decode audio");
+                assert_eq!(decoded.sample_rate, 44100);
+                assert_eq!(decoded.channels, 2);
+                assert_eq!(decoded.frames(), audio.frames());
+            }
+        }
+    }
+}
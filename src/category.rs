@@ -0,0 +1,444 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use colored::Colorize;
+use rand::RngExt;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rayon::prelude::*;
+
+use crate::input::{self, InputFiles};
+use crate::{tags, utils};
+
+/// Category name for tracks without a category.
+const UNCATEGORIZED: &str = "(none)";
+
+/// Sidecar file in the input directory that lists the tracks of each category.
+pub const CATEGORY_FILE: &str = "categories.toml";
+
+/// Where the category of each track comes from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum CategorySource {
+    /// Top-level subfolder of the input directory
+    #[default]
+    Folder,
+    /// Genre tag
+    Genre,
+    /// Sidecar TOML file with a list of tracks for each category
+    File,
+}
+
+/// How the track order uses the categories.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CategoryOrder {
+    /// Take one track from each category in turn
+    Rotate,
+    /// Never play two tracks from the same category in a row
+    Separate,
+}
+
+/// How each permutation is put together from the categories.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Layout {
+    /// All tracks in the given category order
+    Order(CategoryOrder),
+    /// One random track from the named category for each slot
+    Pattern(Vec<String>),
+}
+
+/// Category options for the track order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CategoryOptions {
+    pub source: CategorySource,
+    /// Sidecar category file instead of the one in the input directory
+    pub file: Option<PathBuf>,
+    pub layout: Layout,
+}
+
+/// Input tracks grouped by category.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Categories {
+    layout: Layout,
+    /// Tracks of each category, sorted by category name
+    groups: BTreeMap<String, Vec<PathBuf>>,
+}
+
+impl Layout {
+    /// Parse a comma-separated pattern such as `slow,fast,medium`.
+    pub fn pattern(pattern: &str) -> Result<Self> {
+        let slots: Vec<String> = pattern.split(',').map(normalize).collect();
+        if slots.iter().any(String::is_empty) {
+            anyhow::bail!("Category pattern has an empty slot: '{pattern}'");
+        }
+        Ok(Self::Pattern(slots))
+    }
+}
+
+impl Categories {
+    /// Read the category of each track from the configured source.
+    ///
+    /// # Errors
+    /// Returns an error if the category file cannot be read,
+    /// or the tracks cannot be arranged with the layout.
+    pub fn load(input: &InputFiles, options: &CategoryOptions) -> Result<Self> {
+        let files = &input.files;
+        let categories: Vec<Option<String>> = match options.source {
+            CategorySource::Folder => files
+                .iter()
+                .map(|file| input::subfolder_name(input.source_of(file)?.root(), file))
+                .collect(),
+            CategorySource::Genre => files
+                .par_iter()
+                .map(|file| tags::read_file_info(file).ok().and_then(|info| info.genre))
+                .collect(),
+            CategorySource::File => {
+                let path = options
+                    .file
+                    .clone()
+                    .unwrap_or_else(|| input.inputs[0].root().join(CATEGORY_FILE));
+                read_category_file(&path, input)?
+            }
+        };
+        let mut groups: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+        for (file, category) in files.iter().zip(categories) {
+            let category = category
+                .map(|category| normalize(&category))
+                .filter(|category| !category.is_empty())
+                .unwrap_or_else(|| UNCATEGORIZED.to_string());
+            groups.entry(category).or_default().push(file.clone());
+        }
+        Self::new(groups, options.layout.clone())
+    }
+
+    fn new(groups: BTreeMap<String, Vec<PathBuf>>, layout: Layout) -> Result<Self> {
+        let categories = Self { layout, groups };
+        categories.check()?;
+        Ok(categories)
+    }
+
+    /// Check that the tracks can be arranged with the layout.
+    fn check(&self) -> Result<()> {
+        match &self.layout {
+            Layout::Order(CategoryOrder::Rotate) => {}
+            Layout::Order(CategoryOrder::Separate) => {
+                let total: usize = self.groups.values().map(Vec::len).sum();
+                if let Some((name, tracks)) = self.groups.iter().max_by_key(|(_, tracks)| tracks.len())
+                    && tracks.len() > total.div_ceil(2)
+                {
+                    anyhow::bail!(
+                        "Category '{name}' has {} of {total} tracks, too many to keep them apart",
+                        tracks.len()
+                    );
+                }
+            }
+            Layout::Pattern(slots) => {
+                let mut needed: BTreeMap<&str, usize> = BTreeMap::new();
+                for slot in slots {
+                    *needed.entry(slot).or_default() += 1;
+                }
+                for (name, count) in needed {
+                    let available = self.groups.get(name).map_or(0, Vec::len);
+                    if available < count {
+                        anyhow::bail!(
+                            "Pattern needs {count} '{name}' track(s) but the category has {available}, categories: {}",
+                            self.groups.keys().cloned().collect::<Vec<_>>().join(", ")
+                        );
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of tracks in each permutation.
+    pub fn tracks_per_permutation(&self) -> usize {
+        match &self.layout {
+            Layout::Order(_) => self.groups.values().map(Vec::len).sum(),
+            Layout::Pattern(slots) => slots.len(),
+        }
+    }
+
+    /// Largest number of different track orders, or `None` if there are too many to count.
+    ///
+    /// Patterns pick different tracks of each category for its slots.
+    /// Category orders use all tracks, so this is only an upper limit for them.
+    pub fn unique_orderings(&self) -> Option<usize> {
+        match &self.layout {
+            Layout::Order(_) => {
+                let tracks = self.tracks_per_permutation();
+                utils::k_permutations(tracks, tracks)
+            }
+            Layout::Pattern(slots) => self.groups.iter().try_fold(1, |count: usize, (name, tracks)| {
+                let needed = slots.iter().filter(|slot| *slot == name).count();
+                count.checked_mul(utils::k_permutations(tracks.len(), needed)?)
+            }),
+        }
+    }
+
+    /// Print the number of tracks in each category.
+    pub fn print(&self) {
+        println!("Categories:");
+        let width = self.groups.keys().map(|name| name.chars().count()).max().unwrap_or(0);
+        for (name, tracks) in &self.groups {
+            println!("  {name:<width$}  {} tracks", tracks.len());
+        }
+        if let Layout::Pattern(slots) = &self.layout {
+            println!("Pattern: {}", slots.join(", "));
+        }
+        println!();
+    }
+
+    /// Replace the tracks with a new random order that follows the layout.
+    pub fn arrange(&self, files: &mut Vec<PathBuf>, rng: &mut StdRng) {
+        let mut groups: Vec<Vec<PathBuf>> = self.groups.values().cloned().collect();
+        for group in &mut groups {
+            group.shuffle(rng);
+        }
+        *files = match &self.layout {
+            Layout::Order(CategoryOrder::Rotate) => rotate(groups, rng),
+            Layout::Order(CategoryOrder::Separate) => separate(groups, rng),
+            Layout::Pattern(slots) => {
+                let mut groups: HashMap<&String, Vec<PathBuf>> = self.groups.keys().zip(groups).collect();
+                slots.iter().filter_map(|slot| groups.get_mut(slot)?.pop()).collect()
+            }
+        };
+    }
+}
+
+/// Category names are matched without case and surrounding whitespace.
+fn normalize(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+/// Read the category of each track from a TOML file with a list of tracks for each category:
+///
+/// ```toml
+/// slow = ["Artist - Song.mp3", "Other Artist - Ballad.flac"]
+/// fast = ["subfolder/Artist - Tune.wav"]
+/// ```
+///
+/// Tracks are given as file names or paths relative to their input directory or playlist.
+/// Listed tracks that are not in the input files are reported as warnings.
+fn read_category_file(path: &Path, input: &InputFiles) -> Result<Vec<Option<String>>> {
+    let files = &input.files;
+    let contents = fs::read_to_string(path).context(format!("Failed to read category file: {}", path.display()))?;
+    let listed: BTreeMap<String, Vec<String>> =
+        toml::from_str(&contents).context(format!("Invalid category file: {}", path.display()))?;
+
+    let mut categories = vec![None; files.len()];
+    for (category, tracks) in listed {
+        for track in tracks {
+            let matching: Vec<usize> = files
+                .iter()
+                .enumerate()
+                .filter(|(_, file)| {
+                    input
+                        .source_of(file)
+                        .and_then(|source| file.strip_prefix(source.root()).ok())
+                        .is_some_and(|relative| relative == Path::new(&track))
+                        || file.file_name().is_some_and(|name| name.to_string_lossy() == track)
+                })
+                .map(|(index, _)| index)
+                .collect();
+            if matching.is_empty() {
+                eprintln!("{}", format!("Category file entry not found: {track}").yellow());
+            }
+            for index in matching {
+                categories[index] = Some(category.clone());
+            }
+        }
+    }
+    Ok(categories)
+}
+
+/// Take the categories in a random order, one track per slot.
+/// Categories that run out of tracks are left out of the following rounds.
+fn rotate(mut groups: Vec<Vec<PathBuf>>, rng: &mut StdRng) -> Vec<PathBuf> {
+    groups.shuffle(rng);
+    let rounds = groups.iter().map(Vec::len).max().unwrap_or(0);
+    let mut ordered = Vec::new();
+    for round in 0..rounds {
+        ordered.extend(groups.iter().filter_map(|group| group.get(round).cloned()));
+    }
+    ordered
+}
+
+/// Draw each track from a different category than the previous one,
+/// weighted by how many tracks each category has left.
+///
+/// A category that holds more than half of the remaining tracks has to be drawn next,
+/// otherwise its tracks would end up next to each other.
+fn separate(mut groups: Vec<Vec<PathBuf>>, rng: &mut StdRng) -> Vec<PathBuf> {
+    let total: usize = groups.iter().map(Vec::len).sum();
+    let mut ordered = Vec::with_capacity(total);
+    let mut previous: Option<usize> = None;
+    for remaining in (1..=total).rev() {
+        let forced = groups.iter().position(|group| group.len() * 2 > remaining);
+        let candidates: Vec<usize> = forced.map_or_else(
+            || {
+                (0..groups.len())
+                    .filter(|&index| Some(index) != previous && !groups[index].is_empty())
+                    .collect()
+            },
+            |index| vec![index],
+        );
+        // Only happens if a category has too many tracks, which is checked beforehand
+        let candidates = if candidates.is_empty() {
+            (0..groups.len()).filter(|&index| !groups[index].is_empty()).collect()
+        } else {
+            candidates
+        };
+        let weights: usize = candidates.iter().map(|&index| groups[index].len()).sum();
+        let mut pick = rng.random_range(0..weights);
+        let mut chosen = candidates[0];
+        for &index in &candidates {
+            if pick < groups[index].len() {
+                chosen = index;
+                break;
+            }
+            pick -= groups[index].len();
+        }
+        if let Some(track) = groups[chosen].pop() {
+            ordered.push(track);
+        }
+        previous = Some(chosen);
+    }
+    ordered
+}
+
+#[cfg(test)]
+mod category_tests {
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::input::InputSource;
+
+    fn categories(layout: Layout, counts: &[(&str, usize)]) -> Result<(Categories, Vec<PathBuf>)> {
+        let input = Path::new("/music");
+        let files: Vec<PathBuf> = counts
+            .iter()
+            .flat_map(|&(name, count)| (0..count).map(move |index| input.join(name).join(format!("{index}.mp3"))))
+            .collect();
+        let options = CategoryOptions {
+            source: CategorySource::Folder,
+            file: None,
+            layout,
+        };
+        let pool = InputFiles {
+            files: files.clone(),
+            inputs: vec![InputSource::new(input.to_path_buf())],
+            ..InputFiles::default()
+        };
+        let categories = Categories::load(&pool, &options)?;
+        Ok((categories, files))
+    }
+
+    fn category_names(files: &[PathBuf]) -> Vec<String> {
+        files
+            .iter()
+            .map(|file| input::subfolder_name(Path::new("/music"), file).unwrap_or_default())
+            .collect()
+    }
+
+    #[test]
+    fn rotates_categories_slot_by_slot() {
+        let (categories, mut files) = categories(
+            Layout::Order(CategoryOrder::Rotate),
+            &[("fast", 3), ("medium", 2), ("slow", 3)],
+        )
+        .expect("should group tracks");
+        let mut rng = StdRng::seed_from_u64(3);
+        categories.arrange(&mut files, &mut rng);
+
+        let names = category_names(&files);
+        assert_eq!(names.len(), 8);
+        // The first round sets the order that the following rounds repeat
+        // until the medium category runs out of tracks
+        let round = &names[..3];
+        let without_medium: Vec<String> = round.iter().filter(|name| *name != "medium").cloned().collect();
+        assert_eq!(&names[3..6], round);
+        assert_eq!(names[6..], without_medium);
+    }
+
+    #[test]
+    fn never_places_same_category_next_to_each_other() {
+        let (categories, mut files) = categories(
+            Layout::Order(CategoryOrder::Separate),
+            &[("fast", 6), ("medium", 2), ("slow", 3)],
+        )
+        .expect("should group tracks");
+        let mut rng = StdRng::seed_from_u64(11);
+        for _ in 0..50 {
+            categories.arrange(&mut files, &mut rng);
+            let names = category_names(&files);
+            assert_eq!(names.len(), 11);
+            assert!(names.windows(2).all(|pair| pair[0] != pair[1]), "{names:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_category_with_too_many_tracks() {
+        let counts = [("fast", 3), ("slow", 1)];
+        assert!(categories(Layout::Order(CategoryOrder::Separate), &counts).is_err());
+        assert!(categories(Layout::Order(CategoryOrder::Rotate), &counts).is_ok());
+    }
+
+    #[test]
+    fn fills_pattern_slots_from_matching_categories() {
+        let layout = Layout::pattern("Slow, fast,medium,fast ,slow").expect("should parse pattern");
+        let (categories, mut files) =
+            categories(layout, &[("fast", 4), ("medium", 2), ("slow", 3)]).expect("should group tracks");
+        assert_eq!(categories.tracks_per_permutation(), 5);
+
+        let mut rng = StdRng::seed_from_u64(5);
+        for _ in 0..20 {
+            categories.arrange(&mut files, &mut rng);
+            assert_eq!(category_names(&files), ["slow", "fast", "medium", "fast", "slow"]);
+            let mut unique = files.clone();
+            unique.sort();
+            unique.dedup();
+            assert_eq!(unique.len(), 5);
+        }
+    }
+
+    #[test]
+    fn counts_pattern_orderings_from_slots() {
+        let layout = Layout::pattern("slow,fast,slow").expect("should parse pattern");
+        let (categories, _) = categories(layout, &[("fast", 4), ("slow", 3)]).expect("should group tracks");
+        // 3 * 2 ways to fill the slow slots and 4 for the fast slot
+        assert_eq!(categories.unique_orderings(), Some(24));
+    }
+
+    #[test]
+    fn rejects_pattern_that_needs_more_tracks_than_category_has() {
+        let layout = Layout::pattern("slow,fast,slow").expect("should parse pattern");
+        assert!(categories(layout, &[("fast", 4), ("slow", 1)]).is_err());
+        assert!(Layout::pattern("slow,,fast").is_err());
+    }
+
+    #[test]
+    fn reads_categories_from_sidecar_file() {
+        let dir = tempfile::tempdir().expect("should create temp dir");
+        let files: Vec<PathBuf> = ["Artist A - One.mp3", "Artist B - Two.mp3", "Artist C - Three.mp3"]
+            .iter()
+            .map(|name| dir.path().join(name))
+            .collect();
+        let path = dir.path().join(CATEGORY_FILE);
+        fs::write(
+            &path,
+            "Slow = [\"Artist A - One.mp3\"]\nfast = [\"Artist B - Two.mp3\", \"Missing.mp3\"]\n",
+        )
+        .expect("should write category file");
+
+        let pool = InputFiles {
+            files,
+            inputs: vec![InputSource::new(dir.path().to_path_buf())],
+            ..InputFiles::default()
+        };
+        let categories = read_category_file(&path, &pool).expect("should read category file");
+        assert_eq!(categories, [Some("Slow".to_string()), Some("fast".to_string()), None]);
+    }
+}
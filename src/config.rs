@@ -0,0 +1,343 @@
+use std::num::NonZeroUsize;
+
+use anyhow::Result;
+
+use crate::Args;
+use crate::audio::{AudioFormat, BitDepth};
+use crate::category::{CategoryOptions, CategorySource, Layout};
+use crate::filter::Filter;
+use crate::input::InputSource;
+use crate::naming::{self, NameTemplate};
+use crate::playlist;
+use crate::rules::Rules;
+
+/// Options for generating the randomized permutations.
+#[derive(Debug, Clone)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct Config {
+    /// Number of randomized orders to generate
+    pub permutations: usize,
+    /// Overwrite existing output directories
+    pub overwrite_existing: bool,
+    /// Number of permutations to copy concurrently
+    pub jobs: usize,
+    /// Seed for the random number generator of the run
+    pub seed: u64,
+    /// Event or category name used in output names
+    pub event: String,
+    /// Include audio files from subfolders of the input directory
+    pub recursive: bool,
+    /// Order the tracks by category
+    pub categories: Option<CategoryOptions>,
+    /// Number of tracks to pick for each permutation instead of using all input files
+    pub tracks: Option<usize>,
+    /// Include and exclude filters for the input files
+    pub filter: Filter,
+    /// Decode every input file completely to find corrupt audio data
+    pub verify: bool,
+    /// Leave out broken input files instead of stopping
+    pub skip_broken: bool,
+    /// Find duplicate tracks in the input files
+    pub find_duplicates: bool,
+    /// Keep only the best quality file of each group of duplicates
+    pub keep_best: bool,
+    /// Competition rules the input files must follow
+    pub rules: Option<Rules>,
+    /// Output directory name template
+    pub folder_template: NameTemplate,
+    /// Output file name template
+    pub file_template: NameTemplate,
+    /// Trim each track to an excerpt
+    pub excerpt: Option<Excerpt>,
+    /// Join each permutation into one audio file
+    pub combine: Option<Combine>,
+    /// Detect leading and trailing silence
+    pub silence: Option<SilenceDetection>,
+    /// Print the loudness of each input file
+    pub loudness_report: bool,
+    /// Target integrated loudness in LUFS for normalized copies
+    pub normalize: Option<f64>,
+    /// Convert all output files to one format and sample rate
+    pub convert: bool,
+    /// Audio format for rendered and converted files
+    pub output_format: AudioFormat,
+    /// Sample rate for converted and combined files instead of the highest input sample rate
+    pub sample_rate: Option<u32>,
+    /// Bit depth for rendered and converted files
+    pub bit_depth: BitDepth,
+    /// Write track number, album and album artist tags into the output files
+    pub write_tags: bool,
+    /// Set modification times in playback order for hardware players
+    pub player_order: bool,
+    /// Flush each output file to disk after writing
+    pub fsync: bool,
+    /// Hard link the input files instead of copying them
+    pub link: bool,
+    /// Write a playlist for each permutation instead of copying the files
+    pub playlist_only: bool,
+    /// Verbose output
+    pub verbose: bool,
+}
+
+/// Excerpt to cut from each track, in seconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Excerpt {
+    /// Start position, unless a sidecar CUE file gives the start
+    pub start: f64,
+    pub length: f64,
+    pub fade_in: f64,
+    pub fade_out: f64,
+    /// Draw a random start inside this window for each permutation
+    pub random_window: Option<SafeWindow>,
+}
+
+/// Options for joining the tracks of a permutation into one audio file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Combine {
+    /// Silence between tracks in seconds
+    pub gap: f64,
+    /// Equal-power crossfade between tracks in seconds instead of the gap
+    pub crossfade: Option<f64>,
+    /// Match the levels of the tracks
+    pub match_gain: bool,
+}
+
+/// Options for detecting leading and trailing silence.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SilenceDetection {
+    /// Level in dBFS below which audio counts as silence
+    pub threshold_db: f64,
+    /// Minimum silence length in seconds
+    pub min_duration: f64,
+    /// Remove the detected silence from the output copies
+    pub trim: bool,
+}
+
+/// Part of a track to draw random excerpt starts from, skipping the intro and outro.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SafeWindow {
+    pub skip_intro: f64,
+    pub skip_outro: f64,
+}
+
+impl Config {
+    /// Create config from command line arguments and the resolved permutations count.
+    ///
+    /// # Errors
+    /// Returns an error if the event name, one of the naming templates or the rules file is invalid.
+    pub fn from_args(args: &Args, permutations: usize) -> Result<Self> {
+        // Each combined file is built in memory, so only one permutation is combined at a time
+        let jobs = if args.combine {
+            1
+        } else {
+            args.jobs
+                .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, NonZeroUsize::get))
+                .max(1)
+        };
+
+        naming::validate_event_name(&args.event)?;
+
+        if let Some(sample_rate) = args.sample_rate {
+            if args.convert_to.is_none() && !args.combine {
+                anyhow::bail!("Sample rate requires --convert-to or --combine");
+            }
+            if !(8000..=384_000).contains(&sample_rate) {
+                anyhow::bail!("Sample rate must be between 8000 and 384000 Hz: {sample_rate}");
+            }
+        }
+
+        if args.tracks.is_none() && args.inputs.iter().any(InputSource::is_weighted) {
+            anyhow::bail!("Input weights and must include flags require --tracks");
+        }
+
+        if let Some(target) = args.normalize
+            && !(target.is_finite() && (-70.0..=0.0).contains(&target))
+        {
+            anyhow::bail!("Normalization target must be between -70 and 0 LUFS: {target}");
+        }
+
+        Ok(Self {
+            permutations,
+            overwrite_existing: args.force,
+            jobs,
+            seed: args.seed.unwrap_or_else(rand::random),
+            event: args.event.clone(),
+            recursive: args.recursive,
+            categories: CategoryOptions::from_args(args)?,
+            tracks: args.tracks,
+            filter: Filter::new(&args.include, &args.exclude, args.exclude_list.as_deref())?,
+            verify: args.verify,
+            skip_broken: args.skip_broken,
+            find_duplicates: args.duplicates || args.keep_best,
+            keep_best: args.keep_best,
+            rules: args.rules.as_deref().map(Rules::load).transpose()?,
+            folder_template: NameTemplate::folder(&args.folder_template)?.portable(args.portable_names),
+            file_template: NameTemplate::file(
+                args.file_template
+                    .as_deref()
+                    .unwrap_or_else(|| naming::default_file_template(&args.event)),
+            )?
+            .portable(args.portable_names),
+            excerpt: Excerpt::from_args(args)?,
+            combine: Combine::from_args(args)?,
+            silence: SilenceDetection::from_args(args)?,
+            loudness_report: args.loudness,
+            normalize: args.normalize,
+            convert: args.convert_to.is_some(),
+            output_format: args.convert_to.unwrap_or(args.render_format),
+            sample_rate: args.sample_rate,
+            bit_depth: args.bit_depth,
+            write_tags: args.tags,
+            player_order: args.player_order,
+            fsync: args.fsync,
+            link: args.link,
+            playlist_only: args.playlist_only,
+            verbose: args.verbose,
+        })
+    }
+
+    /// Returns true if the output files are decoded and rendered instead of copied.
+    pub fn renders_audio(&self) -> bool {
+        self.convert
+            || self.excerpt.is_some()
+            || self.combine.is_some()
+            || self.normalize.is_some()
+            || self.trims_silence()
+    }
+
+    /// Returns true if the output needs space for copies of the audio files.
+    pub const fn writes_audio(&self) -> bool {
+        !(self.link || self.playlist_only)
+    }
+
+    /// Returns true if detected silence is trimmed from the output copies.
+    pub fn trims_silence(&self) -> bool {
+        self.silence.is_some_and(|silence| silence.trim)
+    }
+
+    /// Returns true if the loudness of the input files is measured.
+    pub const fn measure_loudness(&self) -> bool {
+        self.loudness_report || self.normalize.is_some()
+    }
+
+    /// Returns the safe window if excerpts start at random positions.
+    pub fn random_window(&self) -> Option<SafeWindow> {
+        self.excerpt.and_then(|excerpt| excerpt.random_window)
+    }
+}
+
+impl Excerpt {
+    /// Create excerpt options if an excerpt length is given.
+    fn from_args(args: &Args) -> Result<Option<Self>> {
+        let Some(length) = args.excerpt else {
+            return Ok(None);
+        };
+        if !(length.is_finite() && length > 0.0) {
+            anyhow::bail!("Excerpt length must be a positive number of seconds: {length}");
+        }
+        for (name, value) in [
+            ("Excerpt start", args.excerpt_start),
+            ("Fade-in", args.fade_in),
+            ("Fade-out", args.fade_out),
+            ("Skipped intro", args.skip_intro),
+            ("Skipped outro", args.skip_outro),
+        ] {
+            if !(value.is_finite() && value >= 0.0) {
+                anyhow::bail!("{name} must be zero or a positive number of seconds: {value}");
+            }
+        }
+        Ok(Some(Self {
+            start: args.excerpt_start,
+            length,
+            fade_in: args.fade_in,
+            fade_out: args.fade_out,
+            random_window: args.random_start.then_some(SafeWindow {
+                skip_intro: args.skip_intro,
+                skip_outro: args.skip_outro,
+            }),
+        }))
+    }
+}
+
+impl CategoryOptions {
+    /// Create category options if the tracks are ordered by category.
+    fn from_args(args: &Args) -> Result<Option<Self>> {
+        let layout = match (args.category_order, &args.pattern) {
+            (Some(order), _) => Layout::Order(order),
+            (None, Some(pattern)) => Layout::pattern(pattern)?,
+            (None, None) => {
+                if args.categories.is_some() || args.category_file.is_some() {
+                    anyhow::bail!("Categories require --category-order or --pattern");
+                }
+                return Ok(None);
+            }
+        };
+        let source = if args.category_file.is_some() {
+            CategorySource::File
+        } else {
+            args.categories.unwrap_or_default()
+        };
+        // Playlists list tracks in subfolders anyway, only directory inputs need to be read recursively
+        let has_directory_input = args
+            .input_dir
+            .as_deref()
+            .map_or(args.inputs.is_empty(), |path| !playlist::is_playlist(path))
+            || args.inputs.iter().any(|input| !playlist::is_playlist(&input.path));
+        if source == CategorySource::Folder && has_directory_input && !args.recursive {
+            anyhow::bail!("Folder categories require --recursive for directory inputs");
+        }
+        Ok(Some(Self {
+            source,
+            file: args.category_file.clone(),
+            layout,
+        }))
+    }
+}
+
+impl Combine {
+    /// Create combine options if combined output is enabled.
+    fn from_args(args: &Args) -> Result<Option<Self>> {
+        if !args.combine {
+            return Ok(None);
+        }
+        if !(args.gap.is_finite() && args.gap >= 0.0) {
+            anyhow::bail!("Gap must be zero or a positive number of seconds: {}", args.gap);
+        }
+        if let Some(crossfade) = args.crossfade
+            && !(crossfade.is_finite() && crossfade > 0.0)
+        {
+            anyhow::bail!("Crossfade must be a positive number of seconds: {crossfade}");
+        }
+        Ok(Some(Self {
+            gap: args.gap,
+            crossfade: args.crossfade,
+            match_gain: args.match_gain,
+        }))
+    }
+}
+
+impl SilenceDetection {
+    /// Create silence detection options if detecting or trimming silence.
+    fn from_args(args: &Args) -> Result<Option<Self>> {
+        if !(args.detect_silence || args.trim_silence) {
+            return Ok(None);
+        }
+        if !(args.silence_threshold.is_finite() && args.silence_threshold < 0.0) {
+            anyhow::bail!(
+                "Silence threshold must be a negative dBFS value: {}",
+                args.silence_threshold
+            );
+        }
+        if !(args.min_silence.is_finite() && args.min_silence >= 0.0) {
+            anyhow::bail!(
+                "Minimum silence must be zero or a positive number of seconds: {}",
+                args.min_silence
+            );
+        }
+        Ok(Some(Self {
+            threshold_db: args.silence_threshold,
+            min_duration: args.min_silence,
+            trim: args.trim_silence,
+        }))
+    }
+}
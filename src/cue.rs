@@ -0,0 +1,220 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::audio::AudioFormat;
+use crate::{tags, utils};
+
+/// CUE sheet timestamps count frames at 75 frames per second.
+const CUE_FRAMES_PER_SECOND: f64 = 75.0;
+
+/// Generated CUE sheet for a combined permutation file.
+#[derive(Debug, Clone)]
+pub struct CueSheet {
+    /// Event name
+    pub performer: String,
+    /// Permutation output name
+    pub title: String,
+    /// Name of the combined audio file next to the CUE sheet
+    pub file_name: String,
+    /// Format of the combined audio file
+    pub format: AudioFormat,
+    pub tracks: Vec<CueTrack>,
+}
+
+/// One track in a generated CUE sheet.
+#[derive(Debug, Clone)]
+pub struct CueTrack {
+    pub performer: Option<String>,
+    pub title: String,
+    /// Track start in the combined file in seconds
+    pub start: f64,
+}
+
+impl CueSheet {
+    /// Render the CUE sheet contents.
+    pub fn render(&self) -> String {
+        let mut lines = vec![
+            format!("PERFORMER {}", quote(&self.performer)),
+            format!("TITLE {}", quote(&self.title)),
+            format!("FILE {} {}", quote(&self.file_name), file_type(self.format)),
+        ];
+        for (index, track) in self.tracks.iter().enumerate() {
+            lines.push(format!("  TRACK {:02} AUDIO", index + 1));
+            lines.push(format!("    TITLE {}", quote(&track.title)));
+            if let Some(performer) = &track.performer {
+                lines.push(format!("    PERFORMER {}", quote(performer)));
+            }
+            lines.push(format!("    INDEX 01 {}", format_timestamp(track.start)));
+        }
+        lines.push(String::new());
+        lines.join("\n")
+    }
+
+    /// Write the CUE sheet next to the combined audio file.
+    pub fn write(&self, audio_path: &Path) -> Result<()> {
+        let path = sidecar_path(audio_path);
+        fs::write(&path, self.render()).context(format!("Failed to write CUE file: {}", path.display()))
+    }
+}
+
+impl CueTrack {
+    /// Create a track entry with artist and title parsed from the file name.
+    ///
+    /// Falls back to the artist and title tags if the file name does not contain the artist.
+    pub fn for_file(path: &Path, start: f64) -> Result<Self> {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let (artist, title) = utils::parse_artist_and_title(&stem);
+        if let Some(artist) = artist {
+            return Ok(Self {
+                performer: Some(artist.to_string()),
+                title: title.to_string(),
+                start,
+            });
+        }
+        let (tag_artist, tag_title) = tags::read_artist_and_title(path)?;
+        Ok(Self {
+            performer: tag_artist.filter(|artist| !artist.is_empty()),
+            title: tag_title
+                .filter(|title| !title.is_empty())
+                .unwrap_or_else(|| title.to_string()),
+            start,
+        })
+    }
+}
+
+/// Path of the sidecar CUE file for the given audio file, for example `Artist - Song.cue`.
+pub fn sidecar_path(audio_path: &Path) -> PathBuf {
+    audio_path.with_extension("cue")
+}
+
+/// Read the start position in seconds from the sidecar CUE file of the given audio file.
+///
+/// Returns `None` if there is no sidecar file.
+pub fn read_sidecar_start(audio_path: &Path) -> Result<Option<f64>> {
+    let path = sidecar_path(audio_path);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let contents = fs::read_to_string(&path).context(format!("Failed to read CUE file: {}", path.display()))?;
+    let start = first_track_start(&contents).context(format!("No INDEX 01 found in CUE file: {}", path.display()))?;
+    Ok(Some(start))
+}
+
+/// Return the position of the first `INDEX 01` entry in seconds.
+pub fn first_track_start(contents: &str) -> Option<f64> {
+    contents.lines().find_map(|line| {
+        let mut parts = line.split_whitespace();
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(keyword), Some("01"), Some(timestamp)) if keyword.eq_ignore_ascii_case("INDEX") => {
+                parse_timestamp(timestamp)
+            }
+            _ => None,
+        }
+    })
+}
+
+/// Parse a CUE timestamp in the format `mm:ss:ff` into seconds.
+pub fn parse_timestamp(timestamp: &str) -> Option<f64> {
+    let mut parts = timestamp.split(':').map(str::parse::<u32>);
+    let (Some(Ok(minutes)), Some(Ok(seconds)), Some(Ok(frames)), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return None;
+    };
+    if seconds >= 60 || f64::from(frames) >= CUE_FRAMES_PER_SECOND {
+        return None;
+    }
+    Some(f64::from(minutes).mul_add(60.0, f64::from(seconds)) + f64::from(frames) / CUE_FRAMES_PER_SECOND)
+}
+
+/// Format seconds as a CUE timestamp in the format `mm:ss:ff`.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub fn format_timestamp(seconds: f64) -> String {
+    let total_frames = (seconds.max(0.0) * CUE_FRAMES_PER_SECOND).round() as u64;
+    let frames_per_second = CUE_FRAMES_PER_SECOND as u64;
+    let frames = total_frames % frames_per_second;
+    let total_seconds = total_frames / frames_per_second;
+    format!("{:02}:{:02}:{frames:02}", total_seconds / 60, total_seconds % 60)
+}
+
+/// File type keyword for the combined audio file in the `FILE` line.
+const fn file_type(format: AudioFormat) -> &'static str {
+    match format {
+        AudioFormat::Wav => "WAVE",
+        AudioFormat::Flac => "FLAC",
+    }
+}
+
+/// Quote a CUE sheet string value.
+///
+/// CUE sheets have no escape sequences, so double quotes are replaced with single quotes.
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "'"))
+}
+
+#[cfg(test)]
+mod cue_tests {
+    use super::*;
+
+    #[test]
+    fn parses_timestamps() {
+        assert_eq!(parse_timestamp("00:00:00"), Some(0.0));
+        assert_eq!(parse_timestamp("01:30:00"), Some(90.0));
+        assert_eq!(parse_timestamp("00:10:15"), Some(10.2));
+        assert_eq!(parse_timestamp("00:61:00"), None);
+        assert_eq!(parse_timestamp("00:10:75"), None);
+        assert_eq!(parse_timestamp("10:00"), None);
+    }
+
+    #[test]
+    fn formats_timestamps() {
+        assert_eq!(format_timestamp(0.0), "00:00:00");
+        assert_eq!(format_timestamp(10.2), "00:10:15");
+        assert_eq!(format_timestamp(3723.5), "62:03:38");
+        assert_eq!(parse_timestamp(&format_timestamp(42.4)), Some(42.4));
+    }
+
+    #[test]
+    fn renders_cue_sheet_with_track_indexes() {
+        let sheet = CueSheet {
+            performer: "FDO Impro".to_string(),
+            title: "FDO Impro 1".to_string(),
+            file_name: "FDO Impro 1.wav".to_string(),
+            format: AudioFormat::Wav,
+            tracks: vec![
+                CueTrack {
+                    performer: Some("Artist A".to_string()),
+                    title: "Song \"One\"".to_string(),
+                    start: 0.0,
+                },
+                CueTrack {
+                    performer: None,
+                    title: "Two".to_string(),
+                    start: 92.0,
+                },
+            ],
+        };
+        let expected = "PERFORMER \"FDO Impro\"\nTITLE \"FDO Impro 1\"\nFILE \"FDO Impro 1.wav\" WAVE\n  TRACK 01 AUDIO\n    TITLE \"Song 'One'\"\n    PERFORMER \"Artist A\"\n    INDEX 01 00:00:00\n  TRACK 02 AUDIO\n    TITLE \"Two\"\n    INDEX 01 01:32:00\n";
+        assert_eq!(sheet.render(), expected);
+
+        let sheet = CueSheet {
+            file_name: "FDO Impro 1.flac".to_string(),
+            format: AudioFormat::Flac,
+            ..sheet
+        };
+        assert!(sheet.render().contains("\nFILE \"FDO Impro 1.flac\" FLAC\n"));
+    }
+
+    #[test]
+    fn finds_first_index_01() {
+        let contents = "FILE \"Artist - Song.mp3\" MP3\n  TRACK 01 AUDIO\n    INDEX 00 00:40:00\n    INDEX 01 00:42:30\n  TRACK 02 AUDIO\n    INDEX 01 03:00:00\n";
+        assert_eq!(first_track_start(contents), Some(42.4));
+    }
+
+    #[test]
+    fn returns_none_without_index() {
+        assert_eq!(first_track_start("FILE \"song.mp3\" MP3\n  TRACK 01 AUDIO\n"), None);
+    }
+}
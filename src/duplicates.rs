@@ -0,0 +1,602 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::hash::{DefaultHasher, Hasher};
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use colored::Colorize;
+use rayon::prelude::*;
+use realfft::RealFftPlanner;
+use realfft::num_complex::Complex;
+
+use crate::audio::{self, AudioBuffer};
+use crate::tags;
+
+/// Length of one fingerprint window in seconds.
+const WINDOW_SECONDS: f64 = 0.1;
+
+/// Largest offset in windows tried when aligning two fingerprints, to allow for different leading silence.
+const MAX_LAG_WINDOWS: usize = 30;
+
+/// Tracks whose durations differ more than this in seconds are not compared.
+///
+/// Matches the largest alignment offset.
+const MAX_DURATION_DIFFERENCE: f64 = 3.0;
+
+/// Fingerprints that correlate at least this much are the same recording.
+const SIMILARITY_THRESHOLD: f64 = 0.9;
+
+/// Fingerprints whose levels vary less than this standard deviation in dB never match.
+const MIN_LEVEL_DEVIATION_DB: f64 = 1.0;
+
+/// Number of frequency bands in each fingerprint window.
+const BAND_COUNT: usize = 32;
+
+/// Frequency range in Hz covered by the bands.
+///
+/// The highest band stays below the low-pass filter of typical MP3 encoders.
+const LOWEST_FREQUENCY: f64 = 100.0;
+const HIGHEST_FREQUENCY: f64 = 8000.0;
+
+/// Quietest level in dB used in fingerprints, so silence does not dominate the correlation.
+const FLOOR_DB: f32 = -90.0;
+
+/// Input files with the same audio, best quality file first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicateGroup {
+    pub files: Vec<Duplicate>,
+}
+
+/// One file in a duplicate group and how it matches the best quality file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Duplicate {
+    pub path: PathBuf,
+    /// Same file contents as the best quality file
+    pub identical: bool,
+    /// Fingerprint similarity with the best quality file between 0 and 1
+    pub similarity: f64,
+}
+
+/// Coarse spectrogram of a recording, used to find the same audio in different files.
+///
+/// Each window holds the level of a few frequency bands of the mono mix,
+/// so different sample rates and codecs of the same recording give nearly the same values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fingerprint {
+    /// Duration in seconds
+    pub duration: f64,
+    windows: Vec<[f32; BAND_COUNT]>,
+}
+
+/// Sort key for picking the best file of a duplicate group.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+struct Quality {
+    lossless: bool,
+    /// Bit depth of lossless files
+    bit_depth: u8,
+    /// Audio bitrate in kbps of lossy files
+    lossy_bitrate: u32,
+    sample_rate: u32,
+}
+
+impl Fingerprint {
+    /// Compute the fingerprint of decoded audio.
+    ///
+    /// Window boundaries are rounded separately so windows stay aligned in time for any sample rate.
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    pub fn from_audio(audio: &AudioBuffer) -> Self {
+        let channels = audio.channels.max(1);
+        let mono: Vec<f32> = audio
+            .samples
+            .chunks_exact(channels)
+            .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+            .collect();
+
+        let window = f64::from(audio.sample_rate) * WINDOW_SECONDS;
+        let fft_size = (window.ceil() as usize).next_power_of_two();
+        let fft = RealFftPlanner::<f32>::new().plan_fft_forward(fft_size);
+        let mut input = fft.make_input_vec();
+        let mut spectrum = fft.make_output_vec();
+        let bin_width = f64::from(audio.sample_rate) / fft_size as f64;
+        let band_bins: [(usize, usize); BAND_COUNT] = std::array::from_fn(|band| {
+            // Bands above the Nyquist frequency of low sample rates stay empty
+            let low = ((band_edge(band) / bin_width) as usize).min(spectrum.len() - 1);
+            let high = (band_edge(band + 1) / bin_width) as usize;
+            (low, high.clamp(low + 1, spectrum.len()))
+        });
+
+        let count = (mono.len() as f64 / window).floor() as usize;
+        let windows = (0..count)
+            .map(|index| {
+                let start = (index as f64 * window).round() as usize;
+                let end = (((index + 1) as f64 * window).round() as usize).min(mono.len());
+                let length = end - start;
+                input.fill(0.0);
+                // Hann window keeps strong tones from leaking into the other bands
+                for (position, (value, sample)) in input.iter_mut().zip(&mono[start..end]).enumerate() {
+                    let phase = std::f32::consts::TAU * position as f32 / length as f32;
+                    *value = sample * 0.5 * (1.0 - phase.cos());
+                }
+                // The input length always matches the planned size
+                let _ = fft.process(&mut input, &mut spectrum);
+                // Scale by both lengths so the band power does not depend on the sample rate
+                let scale = 2.0 / (fft_size * length.max(1)) as f32;
+                band_bins.map(|(low, high)| {
+                    let power: f32 = spectrum[low..high].iter().map(Complex::norm_sqr).sum();
+                    (10.0 * (power * scale).log10()).max(FLOOR_DB)
+                })
+            })
+            .collect();
+
+        Self {
+            duration: audio.duration(),
+            windows,
+        }
+    }
+
+    /// Similarity between 0 and 1 of the two fingerprints at their best alignment.
+    pub fn similarity(&self, other: &Self) -> f64 {
+        if (self.duration - other.duration).abs() > MAX_DURATION_DIFFERENCE {
+            return 0.0;
+        }
+        let mut best: f64 = 0.0;
+        for lag in 0..=MAX_LAG_WINDOWS {
+            for (first, second) in [(self, other), (other, self)] {
+                let shifted = &first.windows[lag.min(first.windows.len())..];
+                best = best.max(correlation(shifted.as_flattened(), second.windows.as_flattened()));
+            }
+        }
+        best
+    }
+}
+
+/// Find groups of input files with the same contents or the same audio.
+///
+/// Files are first grouped by identical contents,
+/// then one file of each group is compared by fingerprint.
+/// Fingerprints from an earlier decode are used if there is one, other files are decoded here.
+pub fn find_duplicates(
+    files: &[PathBuf],
+    fingerprinted: &HashMap<PathBuf, Fingerprint>,
+) -> Result<Vec<DuplicateGroup>> {
+    let contents = content_groups(files)?;
+
+    let mut sets = DisjointSets::new(files.len());
+    for (index, &first) in contents.iter().enumerate() {
+        sets.union(first, index);
+    }
+
+    let unique: Vec<usize> = (0..files.len()).filter(|&index| contents[index] == index).collect();
+    let fingerprints: HashMap<usize, Fingerprint> = unique
+        .par_iter()
+        .map(|&index| {
+            let fingerprint = match fingerprinted.get(&files[index]) {
+                Some(fingerprint) => fingerprint.clone(),
+                None => Fingerprint::from_audio(&audio::decode_file(&files[index])?),
+            };
+            Ok((index, fingerprint))
+        })
+        .collect::<Result<_>>()?;
+    for (position, &first) in unique.iter().enumerate() {
+        for &second in &unique[position + 1..] {
+            if fingerprints[&first].similarity(&fingerprints[&second]) >= SIMILARITY_THRESHOLD {
+                sets.union(first, second);
+            }
+        }
+    }
+
+    let mut groups = Vec::new();
+    for members in sets.groups() {
+        if members.len() < 2 {
+            continue;
+        }
+        let best = best_quality(files, &members);
+        let best_fingerprint = &fingerprints[&contents[best]];
+        let mut duplicates: Vec<Duplicate> = members
+            .iter()
+            .map(|&index| {
+                let identical = contents[index] == contents[best];
+                Duplicate {
+                    path: files[index].clone(),
+                    identical,
+                    similarity: if identical {
+                        1.0
+                    } else {
+                        fingerprints[&contents[index]].similarity(best_fingerprint)
+                    },
+                }
+            })
+            .collect();
+        duplicates.sort_by_key(|duplicate| duplicate.path != files[best]);
+        groups.push(DuplicateGroup { files: duplicates });
+    }
+    Ok(groups)
+}
+
+/// Print duplicate groups and leave out all but the best file of each group if requested.
+pub fn check_duplicates(
+    files: Vec<PathBuf>,
+    fingerprinted: &HashMap<PathBuf, Fingerprint>,
+    keep_best: bool,
+) -> Result<Vec<PathBuf>> {
+    let groups = find_duplicates(&files, fingerprinted)?;
+    if groups.is_empty() {
+        return Ok(files);
+    }
+    println!("{}", format!("Found {} duplicate group(s):", groups.len()).yellow());
+    for group in &groups {
+        for (position, duplicate) in group.files.iter().enumerate() {
+            let name = duplicate.path.file_name().unwrap_or_default().to_string_lossy();
+            if position == 0 {
+                println!("  {name} (best quality)");
+            } else if duplicate.identical {
+                println!("    {name} (identical file)");
+            } else {
+                println!("    {name} (similar audio {:.1}%)", duplicate.similarity * 100.0);
+            }
+        }
+    }
+    println!();
+    if !keep_best {
+        return Ok(files);
+    }
+    let removed: Vec<&PathBuf> = groups
+        .iter()
+        .flat_map(|group| group.files.iter().skip(1).map(|duplicate| &duplicate.path))
+        .collect();
+    println!(
+        "{}",
+        format!(
+            "Keeping only the best quality file, leaving out {} file(s):",
+            removed.len()
+        )
+        .yellow()
+    );
+    for path in &removed {
+        println!("  {}", path.display());
+    }
+    println!();
+    Ok(files.into_iter().filter(|file| !removed.contains(&file)).collect())
+}
+
+/// Returns the index of the best quality file in the group.
+///
+/// Lossless files win over lossy ones, then higher bit depth or bitrate and sample rate.
+/// For equal quality, the shortest file name wins, so "Song.wav" is kept over "Song (1).wav"
+/// wherever the folders are. The full path only decides between equally long names.
+fn best_quality(files: &[PathBuf], members: &[usize]) -> usize {
+    members
+        .iter()
+        .copied()
+        .min_by_key(|&index| {
+            let quality = tags::read_properties(&files[index]).map_or_else(
+                |_| Quality::default(),
+                |properties| Quality {
+                    lossless: properties.bit_depth().is_some(),
+                    bit_depth: properties.bit_depth().unwrap_or_default(),
+                    lossy_bitrate: if properties.bit_depth().is_some() {
+                        0
+                    } else {
+                        properties.audio_bitrate().unwrap_or_default()
+                    },
+                    sample_rate: properties.sample_rate().unwrap_or_default(),
+                },
+            );
+            let name_length = files[index].file_name().unwrap_or_default().len();
+            (Reverse(quality), name_length, &files[index])
+        })
+        .unwrap_or(members[0])
+}
+
+/// Returns for each file the index of the first file with the same contents.
+///
+/// Files with the same size and hash are compared byte by byte,
+/// so a hash collision never makes two different files identical.
+fn content_groups(files: &[PathBuf]) -> Result<Vec<usize>> {
+    let keys: Vec<(u64, u64)> = files
+        .par_iter()
+        .map(|file| {
+            let size = fs::metadata(file)
+                .context(format!("Failed to read file metadata: {}", file.display()))?
+                .len();
+            Ok((size, hash_file(file)?))
+        })
+        .collect::<Result<_>>()?;
+
+    let mut candidates: HashMap<(u64, u64), Vec<usize>> = HashMap::new();
+    let mut contents = Vec::with_capacity(files.len());
+    for (index, key) in keys.into_iter().enumerate() {
+        let firsts = candidates.entry(key).or_default();
+        let mut same = None;
+        for &first in firsts.iter() {
+            if files_equal(&files[first], &files[index])? {
+                same = Some(first);
+                break;
+            }
+        }
+        contents.push(same.unwrap_or_else(|| {
+            firsts.push(index);
+            index
+        }));
+    }
+    Ok(contents)
+}
+
+/// Hash of the file contents, used to find candidates for identical files.
+fn hash_file(path: &Path) -> Result<u64> {
+    let mut reader = open_file(path)?;
+    let mut hasher = DefaultHasher::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = read_chunk(&mut reader, &mut buffer, path)?;
+        if read == 0 {
+            break;
+        }
+        hasher.write(&buffer[..read]);
+    }
+    Ok(hasher.finish())
+}
+
+/// Returns true if both files have the same contents.
+fn files_equal(first: &Path, second: &Path) -> Result<bool> {
+    let mut first_reader = open_file(first)?;
+    let mut second_reader = open_file(second)?;
+    let mut first_buffer = vec![0; 64 * 1024];
+    let mut second_buffer = vec![0; 64 * 1024];
+    loop {
+        let read = read_chunk(&mut first_reader, &mut first_buffer, first)?;
+        if read_chunk(&mut second_reader, &mut second_buffer[..read], second)? != read
+            || first_buffer[..read] != second_buffer[..read]
+        {
+            return Ok(false);
+        }
+        if read == 0 {
+            // The second file must end at the same position
+            return Ok(read_chunk(&mut second_reader, &mut second_buffer[..1], second)? == 0);
+        }
+    }
+}
+
+fn open_file(path: &Path) -> Result<BufReader<File>> {
+    let file = File::open(path).context(format!("Failed to open file: {}", path.display()))?;
+    Ok(BufReader::new(file))
+}
+
+/// Fill the buffer as far as possible and return the number of bytes read, which is 0 at the end of the file.
+fn read_chunk(reader: &mut impl Read, buffer: &mut [u8], path: &Path) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        let read = reader
+            .read(&mut buffer[filled..])
+            .context(format!("Failed to read file: {}", path.display()))?;
+        if read == 0 {
+            break;
+        }
+        filled += read;
+    }
+    Ok(filled)
+}
+
+/// Lower edge in Hz of the given frequency band, spaced evenly on a logarithmic scale.
+fn band_edge(band: usize) -> f64 {
+    #[allow(clippy::cast_precision_loss)]
+    let position = band as f64 / BAND_COUNT as f64;
+    LOWEST_FREQUENCY * (HIGHEST_FREQUENCY / LOWEST_FREQUENCY).powf(position)
+}
+
+/// Pearson correlation of the overlapping start of two level series.
+#[allow(clippy::cast_precision_loss)]
+fn correlation(first: &[f32], second: &[f32]) -> f64 {
+    let overlap = first.len().min(second.len());
+    if overlap < 2 {
+        return 0.0;
+    }
+    let (first, second) = (&first[..overlap], &second[..overlap]);
+    let count = overlap as f64;
+    let first_mean = first.iter().map(|&value| f64::from(value)).sum::<f64>() / count;
+    let second_mean = second.iter().map(|&value| f64::from(value)).sum::<f64>() / count;
+    let (mut covariance, mut first_variance, mut second_variance) = (0.0, 0.0, 0.0);
+    for (&a, &b) in first.iter().zip(second) {
+        let a = f64::from(a) - first_mean;
+        let b = f64::from(b) - second_mean;
+        covariance = a.mul_add(b, covariance);
+        first_variance = a.mul_add(a, first_variance);
+        second_variance = b.mul_add(b, second_variance);
+    }
+    // Steady levels such as test tones carry no information to compare
+    let min_variance = MIN_LEVEL_DEVIATION_DB.powi(2) * count;
+    if first_variance < min_variance || second_variance < min_variance {
+        return 0.0;
+    }
+    covariance / (first_variance * second_variance).sqrt()
+}
+
+/// Union-find for grouping matching files.
+struct DisjointSets {
+    parents: Vec<usize>,
+}
+
+impl DisjointSets {
+    fn new(size: usize) -> Self {
+        Self {
+            parents: (0..size).collect(),
+        }
+    }
+
+    fn find(&mut self, index: usize) -> usize {
+        let parent = self.parents[index];
+        if parent == index {
+            return index;
+        }
+        let root = self.find(parent);
+        self.parents[index] = root;
+        root
+    }
+
+    fn union(&mut self, first: usize, second: usize) {
+        let first_root = self.find(first);
+        let second_root = self.find(second);
+        self.parents[second_root.max(first_root)] = second_root.min(first_root);
+    }
+
+    /// Members of each set in index order, ordered by their first member.
+    fn groups(&mut self) -> Vec<Vec<usize>> {
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        let mut group_of_root: HashMap<usize, usize> = HashMap::new();
+        for index in 0..self.parents.len() {
+            let root = self.find(index);
+            let group = *group_of_root.entry(root).or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+            groups[group].push(index);
+        }
+        groups
+    }
+}
+
+#[cfg(test)]
+mod duplicates_tests {
+    use rand::rngs::StdRng;
+    use rand::{RngExt, SeedableRng};
+
+    use super::*;
+    use crate::audio::AudioFormat;
+
+    /// Noise with a random level every 100 ms, like the changing loudness of music.
+    fn song(seed: u64, seconds: usize) -> AudioBuffer {
+        let sample_rate = 22050;
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut samples = Vec::new();
+        for _ in 0..seconds * 10 {
+            let level: f32 = rng.random_range(0.01..0.8);
+            let tone: f32 = rng.random_range(0.0..1.0);
+            let mut previous = 0.0;
+            for _ in 0..sample_rate / 10 {
+                // Mix of white noise and smoothed noise changes the high frequency content too
+                let noise: f32 = rng.random_range(-1.0..1.0);
+                previous = tone.mul_add(previous - noise, noise);
+                samples.extend([previous * level, previous * level]);
+            }
+        }
+        AudioBuffer {
+            sample_rate,
+            channels: 2,
+            samples,
+        }
+    }
+
+    #[test]
+    fn matches_same_audio_in_different_format() {
+        let original = song(1, 12);
+        let mut delayed = AudioBuffer::empty(original.sample_rate, original.channels);
+        delayed.append_silence(0.5);
+        delayed.append(&original).expect("should append");
+        let converted = delayed.convert(44100, 1).expect("should convert");
+
+        let fingerprint = Fingerprint::from_audio(&original);
+        assert!(fingerprint.similarity(&Fingerprint::from_audio(&converted)) > SIMILARITY_THRESHOLD);
+        assert!(fingerprint.similarity(&Fingerprint::from_audio(&song(2, 12))) < 0.5);
+    }
+
+    #[test]
+    fn groups_duplicates_with_best_quality_first() {
+        let dir = tempfile::tempdir().expect("should create temp dir");
+        let wav = dir.path().join("Artist - Song.wav");
+        let copy = dir.path().join("Artist - Song (1).wav");
+        let flac = dir.path().join("Artist - Song.flac");
+        let other = dir.path().join("Artist - Other.wav");
+        let audio = song(3, 10);
+        audio::write_audio_file(&wav, &audio, AudioFormat::Wav, 16).expect("should write audio");
+        let resampled = audio.convert(44100, 2).expect("should convert");
+        audio::write_audio_file(&flac, &resampled, AudioFormat::Flac, 16).expect("should write audio");
+        std::fs::copy(&wav, &copy).expect("should copy file");
+        audio::write_audio_file(&other, &song(4, 10), AudioFormat::Wav, 16).expect("should write audio");
+
+        let files = vec![other.clone(), copy, flac.clone(), wav];
+        let groups = find_duplicates(&files, &HashMap::new()).expect("should find duplicates");
+        assert_eq!(groups.len(), 1);
+        let group = &groups[0].files;
+        assert_eq!(group.len(), 3);
+        assert_eq!(group[0].path, flac);
+        assert!(group[0].identical);
+        assert!(
+            group[1..]
+                .iter()
+                .all(|duplicate| !duplicate.identical && duplicate.similarity >= SIMILARITY_THRESHOLD)
+        );
+
+        let kept = check_duplicates(files, &HashMap::new(), true).expect("should keep best");
+        assert_eq!(kept, [other, flac]);
+    }
+
+    #[test]
+    fn compares_contents_byte_by_byte() {
+        let dir = tempfile::tempdir().expect("should create temp dir");
+        let write = |name: &str, contents: &[u8]| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, contents).expect("should write file");
+            path
+        };
+        let long: Vec<u8> = (0..200_000).map(|value: u32| value.to_le_bytes()[0]).collect();
+        let mut changed = long.clone();
+        changed[150_000] ^= 1;
+        let files = [
+            write("a", &long),
+            write("b", &changed),
+            write("c", &long),
+            write("d", &long[..100_000]),
+        ];
+
+        assert!(files_equal(&files[0], &files[2]).expect("should compare"));
+        assert!(!files_equal(&files[0], &files[1]).expect("should compare"));
+        assert!(!files_equal(&files[0], &files[3]).expect("should compare"));
+        assert!(!files_equal(&files[3], &files[0]).expect("should compare"));
+        assert_eq!(content_groups(&files).expect("should group"), [0, 1, 0, 3]);
+    }
+
+    #[test]
+    fn prefers_shorter_file_name_over_shorter_path() {
+        let dir = tempfile::tempdir().expect("should create temp dir");
+        let deep = dir.path().join("a much longer folder name");
+        std::fs::create_dir(&deep).expect("should create dir");
+        let plain = deep.join("Artist - Song.wav");
+        let copy = dir.path().join("Artist - Song (1).wav");
+        let audio = song(5, 1);
+        for path in [&plain, &copy] {
+            audio::write_audio_file(path, &audio, AudioFormat::Wav, 16).expect("should write audio");
+        }
+
+        let files = vec![copy, plain.clone()];
+        assert_eq!(files[best_quality(&files, &[0, 1])], plain);
+    }
+
+    #[test]
+    fn picks_lossless_and_plain_names_first() {
+        let lossy = Quality {
+            lossless: false,
+            bit_depth: 0,
+            lossy_bitrate: 320,
+            sample_rate: 48000,
+        };
+        let lossless = Quality {
+            lossless: true,
+            bit_depth: 16,
+            lossy_bitrate: 0,
+            sample_rate: 44100,
+        };
+        assert!(lossless > lossy);
+        assert!(
+            Quality {
+                bit_depth: 24,
+                ..lossless
+            } > lossless
+        );
+    }
+}
@@ -0,0 +1,211 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use globset::{Glob, GlobMatcher};
+use regex::{Regex, RegexBuilder};
+
+use crate::input::SkipReason;
+use crate::utils;
+
+/// Prefix for patterns that are regular expressions instead of globs.
+const REGEX_PREFIX: &str = "re:";
+
+/// Prefix for exclusion list lines that ban an artist instead of one track.
+const ARTIST_PREFIX: &str = "artist:";
+
+/// Include and exclude filters for the input pool.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+    /// Banned file names, stems and relative paths in lowercase
+    banned_tracks: Vec<String>,
+    /// Banned artist names in lowercase
+    banned_artists: Vec<String>,
+}
+
+/// Case-insensitive glob or regular expression.
+#[derive(Debug, Clone)]
+enum Pattern {
+    Glob(GlobMatcher),
+    Regex(Regex),
+}
+
+impl Pattern {
+    /// Parse a glob, or a regular expression with the `re:` prefix.
+    fn parse(pattern: &str) -> Result<Self> {
+        if let Some(regex) = pattern.strip_prefix(REGEX_PREFIX) {
+            let regex = RegexBuilder::new(regex)
+                .case_insensitive(true)
+                .build()
+                .context(format!("Invalid regular expression: '{regex}'"))?;
+            return Ok(Self::Regex(regex));
+        }
+        let glob = globset::GlobBuilder::new(pattern)
+            .case_insensitive(true)
+            .build()
+            .map(|glob: Glob| glob.compile_matcher())
+            .context(format!("Invalid glob pattern: '{pattern}'"))?;
+        Ok(Self::Glob(glob))
+    }
+
+    /// Globs must match the whole text, regular expressions any part of it.
+    fn is_match(&self, text: &str) -> bool {
+        match self {
+            Self::Glob(glob) => glob.is_match(text),
+            Self::Regex(regex) => regex.is_match(text),
+        }
+    }
+}
+
+impl Filter {
+    /// Create filters from the include and exclude patterns and the exclusion list file.
+    ///
+    /// # Errors
+    /// Returns an error if a pattern is invalid or the exclusion list cannot be read.
+    pub fn new(include: &[String], exclude: &[String], exclude_list: Option<&Path>) -> Result<Self> {
+        let mut filter = Self {
+            include: include
+                .iter()
+                .map(|pattern| Pattern::parse(pattern))
+                .collect::<Result<_>>()?,
+            exclude: exclude
+                .iter()
+                .map(|pattern| Pattern::parse(pattern))
+                .collect::<Result<_>>()?,
+            ..Self::default()
+        };
+        if let Some(path) = exclude_list {
+            let contents =
+                fs::read_to_string(path).context(format!("Failed to read exclusion list: {}", path.display()))?;
+            filter.add_exclusions(&contents);
+        }
+        Ok(filter)
+    }
+
+    /// Add the tracks and artists of an exclusion list.
+    ///
+    /// Each line is a track as a file name, file name without extension or relative path,
+    /// or an artist with the `artist:` prefix. Empty lines and lines starting with `#` are ignored.
+    fn add_exclusions(&mut self, contents: &str) {
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.strip_prefix(ARTIST_PREFIX) {
+                Some(artist) => self.banned_artists.push(artist.trim().to_lowercase()),
+                None => self.banned_tracks.push(line.replace('\\', "/").to_lowercase()),
+            }
+        }
+    }
+
+    /// Returns true if no filters are set.
+    pub const fn is_empty(&self) -> bool {
+        self.include.is_empty()
+            && self.exclude.is_empty()
+            && self.banned_tracks.is_empty()
+            && self.banned_artists.is_empty()
+    }
+
+    /// Returns the reason to leave out the file, or `None` if it passes the filters.
+    ///
+    /// Patterns are matched against the file name and the path relative to the input directory.
+    /// The artist is parsed from the file name unless one is given.
+    pub fn skip_reason(&self, root: &Path, file: &Path, artist: Option<&str>) -> Option<SkipReason> {
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        let relative = file.strip_prefix(root).map_or_else(
+            |_| name.to_string(),
+            |relative| relative.to_string_lossy().replace('\\', "/"),
+        );
+        let matches = |pattern: &Pattern| pattern.is_match(&name) || pattern.is_match(&relative);
+
+        if !self.include.is_empty() && !self.include.iter().any(matches) {
+            return Some(SkipReason::NotIncluded);
+        }
+        if self.exclude.iter().any(matches) {
+            return Some(SkipReason::Excluded);
+        }
+
+        let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+        let (name, stem, relative) = (name.to_lowercase(), stem.to_lowercase(), relative.to_lowercase());
+        if self
+            .banned_tracks
+            .iter()
+            .any(|track| *track == name || *track == stem || *track == relative)
+        {
+            return Some(SkipReason::Banned);
+        }
+        let artist = artist
+            .map(str::to_lowercase)
+            .or_else(|| utils::parse_artist_and_title(&stem).0.map(str::to_string));
+        if let Some(artist) = artist
+            && self.banned_artists.contains(&artist)
+        {
+            return Some(SkipReason::Banned);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod filter_tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn patterns(patterns: &[&str]) -> Vec<String> {
+        patterns.iter().map(ToString::to_string).collect()
+    }
+
+    fn reason(filter: &Filter, file: &str) -> Option<SkipReason> {
+        filter.skip_reason(Path::new("/music"), &PathBuf::from("/music").join(file), None)
+    }
+
+    #[test]
+    fn filters_with_globs_and_regex() {
+        let filter = Filter::new(
+            &patterns(&["slow/*", "re:^artist b - "]),
+            &patterns(&["*(live)*"]),
+            None,
+        )
+        .expect("should create filter");
+
+        assert_eq!(reason(&filter, "slow/Artist A - Song.mp3"), None);
+        assert_eq!(reason(&filter, "fast/Artist B - Song.mp3"), None);
+        assert_eq!(
+            reason(&filter, "fast/Artist A - Song.mp3"),
+            Some(SkipReason::NotIncluded)
+        );
+        assert_eq!(
+            reason(&filter, "SLOW/Artist A - Song (Live).mp3"),
+            Some(SkipReason::Excluded)
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        assert!(Filter::new(&patterns(&["re:(unclosed"]), &[], None).is_err());
+        assert!(Filter::new(&[], &patterns(&["[unclosed"]), None).is_err());
+    }
+
+    #[test]
+    fn leaves_out_banned_tracks_and_artists() {
+        let mut filter = Filter::default();
+        filter
+            .add_exclusions("# Banned this season\nArtist A - Overplayed\nslow/Other - Song.wav\n\nartist: Artist C\n");
+        assert!(!filter.is_empty());
+
+        assert_eq!(reason(&filter, "Artist A - Overplayed.mp3"), Some(SkipReason::Banned));
+        assert_eq!(reason(&filter, "slow/Other - Song.wav"), Some(SkipReason::Banned));
+        assert_eq!(reason(&filter, "fast/Other - Song.wav"), None);
+        assert_eq!(reason(&filter, "artist c - Anything.flac"), Some(SkipReason::Banned));
+        assert_eq!(reason(&filter, "Artist A - Fine.mp3"), None);
+        // Artist from a playlist entry instead of the file name
+        let file = Path::new("/music/01 Track.mp3");
+        assert_eq!(
+            filter.skip_reason(Path::new("/music"), file, Some("Artist C")),
+            Some(SkipReason::Banned)
+        );
+    }
+}
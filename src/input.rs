@@ -0,0 +1,700 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use colored::Colorize;
+use rand::RngExt;
+use rand::rngs::StdRng;
+
+use crate::filter::Filter;
+use crate::playlist::{self, TrackName};
+use crate::utils;
+
+/// File names that operating systems and file managers create in every folder.
+static OS_METADATA_FILES: [&str; 6] = [
+    ".DS_Store",
+    ".localized",
+    "Thumbs.db",
+    "ehthumbs.db",
+    "desktop.ini",
+    "Icon\r",
+];
+
+/// Folders that operating systems and archive tools create and that never hold usable tracks.
+static OS_METADATA_DIRS: [&str; 4] = ["__MACOSX", "$RECYCLE.BIN", "System Volume Information", "@eaDir"];
+
+/// Extensions browsers and download tools use for unfinished downloads.
+static PARTIAL_DOWNLOAD_EXTENSIONS: [&str; 4] = ["crdownload", "download", "part", "partial"];
+
+/// Input directory or playlist with its share of the tracks in each permutation.
+///
+/// Parsed from `DIR`, `DIR@WEIGHT` or `DIR@must`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputSource {
+    pub path: PathBuf,
+    /// Relative chance for the tracks of this input to be picked
+    pub weight: f64,
+    /// Every track of this input is in every permutation
+    pub must: bool,
+}
+
+/// Random pick of tracks from weighted inputs for permutations that do not use all input files.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selection {
+    tracks: usize,
+    /// Tracks from inputs that must be included
+    must: Vec<PathBuf>,
+    /// Other tracks with the weight of their input
+    weighted: Vec<(PathBuf, f64)>,
+}
+
+/// Audio files found in the inputs and the files that were left out.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InputFiles {
+    pub files: Vec<PathBuf>,
+    pub skipped: Vec<SkippedFile>,
+    /// Inputs the files were gathered from
+    pub inputs: Vec<InputSource>,
+    /// Index of the input each file was gathered from
+    pub sources: HashMap<PathBuf, usize>,
+    /// Artist and title that playlists give the files
+    pub names: HashMap<PathBuf, TrackName>,
+}
+
+/// File in the input directory that looks like audio or clutter but is not used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedFile {
+    pub path: PathBuf,
+    pub reason: SkipReason,
+}
+
+/// Why a file in the input directory was left out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// macOS resource fork file such as `._Artist - Song.mp3`
+    AppleDouble,
+    /// Name starts with a dot
+    Hidden,
+    /// Zero-length file
+    Empty,
+    /// Unfinished download or its placeholder file
+    PartialDownload,
+    /// Folder metadata such as `.DS_Store` or `Thumbs.db`
+    OsMetadata,
+    /// Does not match any `--include` pattern
+    NotIncluded,
+    /// Matches an `--exclude` pattern
+    Excluded,
+    /// Track or artist is on the exclusion list
+    Banned,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            Self::AppleDouble => "AppleDouble resource fork",
+            Self::Hidden => "hidden file",
+            Self::Empty => "empty file",
+            Self::PartialDownload => "partial download",
+            Self::OsMetadata => "OS metadata",
+            Self::NotIncluded => "not included",
+            Self::Excluded => "excluded",
+            Self::Banned => "on exclusion list",
+        };
+        write!(f, "{reason}")
+    }
+}
+
+impl InputSource {
+    /// Input directory used with its default weight.
+    pub const fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            weight: 1.0,
+            must: false,
+        }
+    }
+
+    /// Directory that relative paths and subfolders start from: the input directory or the playlist location.
+    pub fn root(&self) -> &Path {
+        if self.is_playlist() {
+            self.path.parent().unwrap_or(&self.path)
+        } else {
+            &self.path
+        }
+    }
+
+    pub fn is_playlist(&self) -> bool {
+        self.path.is_file() && playlist::is_playlist(&self.path)
+    }
+
+    /// Returns true if the input has a weight or must flag.
+    pub fn is_weighted(&self) -> bool {
+        self.must || (self.weight - 1.0).abs() > f64::EPSILON
+    }
+}
+
+impl FromStr for InputSource {
+    type Err = String;
+
+    /// Only a suffix that is a number or `must` is split off,
+    /// so directory names that contain `@` work as is.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let Some((path, suffix)) = value.rsplit_once('@') {
+            if suffix.eq_ignore_ascii_case("must") {
+                return Ok(Self {
+                    must: true,
+                    ..Self::new(PathBuf::from(path))
+                });
+            }
+            if let Ok(weight) = suffix.parse::<f64>() {
+                if !(weight.is_finite() && weight > 0.0) {
+                    return Err(format!("weight must be a positive number: {suffix}"));
+                }
+                return Ok(Self {
+                    weight,
+                    ..Self::new(PathBuf::from(path))
+                });
+            }
+        }
+        Ok(Self::new(PathBuf::from(value)))
+    }
+}
+
+impl fmt::Display for InputSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path.display())?;
+        if self.must {
+            write!(f, " @must")
+        } else if self.is_weighted() {
+            write!(f, " @{}", self.weight)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Selection {
+    /// Pick the given number of tracks for each permutation from the input files.
+    ///
+    /// # Errors
+    /// Returns an error if there are not enough input files,
+    /// or the inputs that must be included have more tracks than fit in a permutation.
+    pub fn new(input: &InputFiles, tracks: usize) -> Result<Self> {
+        let files = &input.files;
+        if tracks == 0 {
+            anyhow::bail!("Number of tracks must be at least one");
+        }
+        if tracks > files.len() {
+            anyhow::bail!("Only {} input files for {tracks} tracks", files.len());
+        }
+        let mut must = Vec::new();
+        let mut weighted = Vec::new();
+        for file in files {
+            match input.source_of(file) {
+                Some(input) if input.must => must.push(file.clone()),
+                Some(input) => weighted.push((file.clone(), input.weight)),
+                None => weighted.push((file.clone(), 1.0)),
+            }
+        }
+        if must.len() > tracks {
+            anyhow::bail!(
+                "Inputs that must be included have {} tracks, more than {tracks} tracks",
+                must.len()
+            );
+        }
+        Ok(Self { tracks, must, weighted })
+    }
+
+    pub const fn tracks(&self) -> usize {
+        self.tracks
+    }
+
+    /// Number of different track orders the picks can give, or `None` if there are too many to count.
+    ///
+    /// Every order holds all tracks that must be included and a pick of the other tracks.
+    pub fn unique_orderings(&self) -> Option<usize> {
+        let picks = utils::binomial(self.weighted.len(), self.tracks - self.must.len())?;
+        picks.checked_mul(utils::k_permutations(self.tracks, self.tracks)?)
+    }
+
+    /// Draw the tracks for one permutation, in no particular order.
+    pub fn pick(&self, rng: &mut StdRng) -> Vec<PathBuf> {
+        let mut picked = self.must.clone();
+        let mut weighted = self.weighted.clone();
+        while picked.len() < self.tracks && !weighted.is_empty() {
+            let total: f64 = weighted.iter().map(|(_, weight)| weight).sum();
+            let mut target = rng.random_range(0.0..total);
+            let index = weighted
+                .iter()
+                .position(|(_, weight)| {
+                    target -= weight;
+                    target < 0.0
+                })
+                .unwrap_or(weighted.len() - 1);
+            picked.push(weighted.swap_remove(index).0);
+        }
+        picked
+    }
+}
+
+impl InputFiles {
+    /// Returns the input the file was gathered from.
+    ///
+    /// Files that were not gathered by [`gather_inputs`] are matched by path,
+    /// and with nested inputs the innermost input is used.
+    pub fn source_of(&self, file: &Path) -> Option<&InputSource> {
+        if let Some(&index) = self.sources.get(file) {
+            return self.inputs.get(index);
+        }
+        self.inputs
+            .iter()
+            .filter(|input| file.starts_with(input.root()))
+            .max_by_key(|input| input.root().components().count())
+    }
+
+    /// Print the skipped files with the reason for each.
+    pub fn print_skipped(&self) {
+        if self.skipped.is_empty() {
+            return;
+        }
+        println!("Skipped files:");
+        for skipped in &self.skipped {
+            println!(
+                "  {} ({})",
+                skipped.path.file_name().unwrap_or_default().to_string_lossy(),
+                skipped.reason
+            );
+        }
+        println!();
+    }
+}
+
+/// Get a list of all the audio files present in the input path.
+/// Note: only looks at the files directly in the given directory unless `recursive` is set.
+/// Recursion skips hidden folders and folders created by the operating system.
+///
+/// Resource fork, hidden, empty and partially downloaded files and OS metadata are skipped.
+/// Other files without an audio extension are ignored silently.
+pub fn gather_audio_files(input_path: &Path, recursive: bool) -> Result<InputFiles> {
+    let mut input = InputFiles::default();
+    gather_directory(input_path, recursive, &mut input, &mut HashSet::new())
+        .context(format!("Failed to read input directory: {}", input_path.display()))?;
+
+    // Sort the files and remove duplicates
+    input.files.sort();
+    input.files.dedup();
+    input.skipped.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(input)
+}
+
+/// Gather the audio files of all the inputs into one pool.
+///
+/// Playlist entries that are missing or are not audio files are reported as warnings.
+/// Files left out by the include, exclude and exclusion list filters are added to the skipped files.
+pub fn gather_inputs(inputs: &[InputSource], recursive: bool, filter: &Filter) -> Result<InputFiles> {
+    let mut pool = InputFiles {
+        inputs: inputs.to_vec(),
+        ..InputFiles::default()
+    };
+    for (index, input) in inputs.iter().enumerate() {
+        let gathered = if input.is_playlist() {
+            gather_playlist(&input.path, &mut pool.names)?
+        } else {
+            gather_audio_files(&input.path, recursive)?
+        };
+        for file in gathered.files {
+            let artist = pool.names.get(&file).and_then(|name| name.artist.as_deref());
+            if let Some(reason) = filter.skip_reason(input.root(), &file, artist) {
+                pool.skipped.push(SkippedFile { path: file, reason });
+                continue;
+            }
+            pool.sources.entry(file.clone()).or_insert(index);
+            pool.files.push(file);
+        }
+        pool.skipped.extend(gathered.skipped);
+    }
+    pool.files.sort();
+    pool.files.dedup();
+    pool.skipped.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(pool)
+}
+
+/// Get the audio files listed in the playlist, with the artist and title of each entry.
+fn gather_playlist(path: &Path, names: &mut HashMap<PathBuf, TrackName>) -> Result<InputFiles> {
+    let playlist = playlist::read_playlist(path)?;
+    let playlist_name = path.file_name().unwrap_or_default().to_string_lossy();
+    for missing in &playlist.missing {
+        eprintln!(
+            "{}",
+            format!("Missing playlist entry in {playlist_name}: {}", missing.display()).yellow()
+        );
+    }
+    for location in &playlist.unsupported {
+        eprintln!(
+            "{}",
+            format!("Unsupported playlist entry in {playlist_name}: {location}").yellow()
+        );
+    }
+
+    let mut input = InputFiles::default();
+    for entry in playlist.entries {
+        match skip_reason(&entry.path) {
+            Some(reason) => input.skipped.push(SkippedFile {
+                path: entry.path,
+                reason,
+            }),
+            None if utils::is_audio_file(&entry.path) => {
+                if let Some(name) = entry.name {
+                    names.insert(entry.path.clone(), name);
+                }
+                input.files.push(entry.path);
+            }
+            None => eprintln!(
+                "{}",
+                format!(
+                    "Playlist entry in {playlist_name} is not an audio file: {}",
+                    entry.path.display()
+                )
+                .yellow()
+            ),
+        }
+    }
+    Ok(input)
+}
+
+/// Distinct names for files that have the same file name in different folders.
+///
+/// The stem gets the name of the nearest parent folder that tells the files apart,
+/// for example `Artist - Song (core)` and `Artist - Song (extras)`.
+/// Files with a unique name are not included.
+pub fn distinct_stems(files: &[PathBuf]) -> HashMap<PathBuf, String> {
+    let mut by_name: HashMap<String, Vec<&PathBuf>> = HashMap::new();
+    for file in files {
+        let name = file.file_name().unwrap_or_default().to_string_lossy().to_lowercase();
+        by_name.entry(name).or_default().push(file);
+    }
+    let mut stems = HashMap::new();
+    for group in by_name.into_values().filter(|group| group.len() > 1) {
+        let max_depth = group.iter().map(|file| file.components().count()).max().unwrap_or(0);
+        let depth = (1..max_depth).find(|&depth| {
+            let mut labels: Vec<String> = group.iter().map(|file| parent_label(file, depth)).collect();
+            labels.sort();
+            labels.dedup();
+            labels.len() == group.len()
+        });
+        for (index, file) in group.into_iter().enumerate() {
+            let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+            let label = depth.map_or_else(|| (index + 1).to_string(), |depth| parent_label(file, depth));
+            stems.insert(file.clone(), format!("{stem} ({label})"));
+        }
+    }
+    stems
+}
+
+/// Name of the folder the given number of levels above the file.
+fn parent_label(file: &Path, depth: usize) -> String {
+    file.ancestors()
+        .nth(depth)
+        .and_then(Path::file_name)
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Returns the name of the top-level subfolder the file is in,
+/// or `None` for files directly in the input directory.
+pub fn subfolder_name(input_path: &Path, file: &Path) -> Option<String> {
+    let relative = file.strip_prefix(input_path).ok()?;
+    let mut components = relative.components();
+    let first = components.next()?;
+    components.next()?;
+    Some(first.as_os_str().to_string_lossy().to_string())
+}
+
+/// Add the files of the directory to the input files.
+///
+/// Symbolic links are followed. Directories already visited through another link are skipped,
+/// so a link to a parent folder does not recurse forever.
+fn gather_directory(
+    directory: &Path,
+    recursive: bool,
+    input: &mut InputFiles,
+    visited: &mut HashSet<PathBuf>,
+) -> Result<()> {
+    if let Ok(canonical) = dunce::canonicalize(directory)
+        && !visited.insert(canonical)
+    {
+        return Ok(());
+    }
+    for entry in fs::read_dir(directory)? {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        let path = entry.path();
+        // Metadata of the link target, so linked files and folders are used like regular ones
+        let Ok(metadata) = fs::metadata(&path) else {
+            continue;
+        };
+        if metadata.is_dir() {
+            if recursive && !is_ignored_directory(&path) {
+                gather_directory(&path, recursive, input, visited)
+                    .context(format!("Failed to read subdirectory: {}", path.display()))?;
+            }
+            continue;
+        }
+        if !metadata.is_file() {
+            continue;
+        }
+        match skip_reason(&path) {
+            Some(reason) => input.skipped.push(SkippedFile { path, reason }),
+            None if utils::is_audio_file(&path) => input.files.push(path),
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+fn is_ignored_directory(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    name.starts_with('.') || OS_METADATA_DIRS.iter().any(|dir| name.eq_ignore_ascii_case(dir))
+}
+
+/// Returns the reason to skip the file, or `None` if it can be used.
+///
+/// Only audio files are checked for zero length and download placeholders,
+/// so sidecar files and other documents are not reported.
+fn skip_reason(path: &Path) -> Option<SkipReason> {
+    let name = path.file_name()?.to_string_lossy();
+    if OS_METADATA_FILES
+        .iter()
+        .any(|metadata| name.eq_ignore_ascii_case(metadata))
+    {
+        return Some(SkipReason::OsMetadata);
+    }
+    if name.starts_with("._") {
+        return Some(SkipReason::AppleDouble);
+    }
+    if name.starts_with('.') {
+        return Some(SkipReason::Hidden);
+    }
+    if is_partial_download(path) {
+        return Some(SkipReason::PartialDownload);
+    }
+    if !utils::is_audio_file(path) {
+        return None;
+    }
+    if fs::metadata(path).is_ok_and(|metadata| metadata.len() == 0) {
+        return Some(SkipReason::Empty);
+    }
+    // Browsers create the final file next to the download, for example `Song.mp3` and `Song.mp3.part`
+    let has_download_marker = PARTIAL_DOWNLOAD_EXTENSIONS.iter().any(|extension| {
+        let mut marker = path.as_os_str().to_owned();
+        marker.push(".");
+        marker.push(extension);
+        Path::new(&marker).exists()
+    });
+    has_download_marker.then_some(SkipReason::PartialDownload)
+}
+
+fn is_partial_download(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| {
+        let extension = extension.to_string_lossy().to_lowercase();
+        PARTIAL_DOWNLOAD_EXTENSIONS.contains(&extension.as_str())
+    })
+}
+
+#[cfg(test)]
+mod input_tests {
+    use super::*;
+
+    fn write(dir: &Path, name: &str, contents: &[u8]) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, contents).expect("should write file");
+        path
+    }
+
+    #[test]
+    fn skips_clutter_and_keeps_audio() {
+        let dir = tempfile::tempdir().expect("should create temp dir");
+        let song = write(dir.path(), "Artist - Song.mp3", b"audio");
+        write(dir.path(), "._Artist - Song.mp3", b"fork");
+        write(dir.path(), ".Hidden - Song.mp3", b"audio");
+        write(dir.path(), "Empty - Song.wav", b"");
+        write(dir.path(), "Partial - Song.mp3", b"audio");
+        write(dir.path(), "Partial - Song.mp3.part", b"audio");
+        write(dir.path(), "Download - Song.flac.crdownload", b"audio");
+        write(dir.path(), ".DS_Store", b"metadata");
+        write(dir.path(), "Thumbs.db", b"metadata");
+        write(dir.path(), "Artist - Song.cue", b"cue");
+        fs::create_dir(dir.path().join("Folder.mp3")).expect("should create dir");
+
+        let input = gather_audio_files(dir.path(), false).expect("should gather files");
+        assert_eq!(input.files, [song]);
+
+        let skipped: Vec<(String, SkipReason)> = input
+            .skipped
+            .iter()
+            .map(|skipped| {
+                let name = skipped.path.file_name().unwrap_or_default().to_string_lossy();
+                (name.to_string(), skipped.reason)
+            })
+            .collect();
+        assert_eq!(
+            skipped,
+            [
+                (".DS_Store".to_string(), SkipReason::OsMetadata),
+                (".Hidden - Song.mp3".to_string(), SkipReason::Hidden),
+                ("._Artist - Song.mp3".to_string(), SkipReason::AppleDouble),
+                (
+                    "Download - Song.flac.crdownload".to_string(),
+                    SkipReason::PartialDownload
+                ),
+                ("Empty - Song.wav".to_string(), SkipReason::Empty),
+                ("Partial - Song.mp3".to_string(), SkipReason::PartialDownload),
+                ("Partial - Song.mp3.part".to_string(), SkipReason::PartialDownload),
+                ("Thumbs.db".to_string(), SkipReason::OsMetadata),
+            ]
+        );
+    }
+
+    #[test]
+    fn gathers_subfolders_when_recursive() {
+        let dir = tempfile::tempdir().expect("should create temp dir");
+        for folder in ["slow", "fast", "fast/extra", ".hidden", "__MACOSX"] {
+            fs::create_dir(dir.path().join(folder)).expect("should create dir");
+        }
+        let root = write(dir.path(), "Root - Song.mp3", b"audio");
+        let slow = write(&dir.path().join("slow"), "Slow - Song.mp3", b"audio");
+        let fast = write(&dir.path().join("fast"), "Fast - Song.mp3", b"audio");
+        let nested = write(&dir.path().join("fast/extra"), "Nested - Song.mp3", b"audio");
+        write(&dir.path().join(".hidden"), "Hidden - Song.mp3", b"audio");
+        write(&dir.path().join("__MACOSX"), "Fork - Song.mp3", b"fork");
+
+        let input = gather_audio_files(dir.path(), false).expect("should gather files");
+        assert_eq!(input.files, std::slice::from_ref(&root));
+
+        let input = gather_audio_files(dir.path(), true).expect("should gather files");
+        let mut expected = vec![root.clone(), slow.clone(), fast, nested.clone()];
+        expected.sort();
+        assert_eq!(input.files, expected);
+
+        assert_eq!(subfolder_name(dir.path(), &root), None);
+        assert_eq!(subfolder_name(dir.path(), &slow).as_deref(), Some("slow"));
+        assert_eq!(subfolder_name(dir.path(), &nested).as_deref(), Some("fast"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn follows_symlinks_to_files_and_folders() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::tempdir().expect("should create temp dir");
+        let library = tempfile::tempdir().expect("should create temp dir");
+        fs::create_dir(library.path().join("fast")).expect("should create dir");
+        let song = write(library.path(), "Linked - Song.mp3", b"audio");
+        write(&library.path().join("fast"), "Fast - Song.mp3", b"audio");
+        symlink(&song, dir.path().join("Linked - Song.mp3")).expect("should link file");
+        symlink(library.path().join("fast"), dir.path().join("fast")).expect("should link dir");
+        // A link back to the parent folder must not recurse forever
+        symlink(dir.path(), dir.path().join("loop")).expect("should link dir");
+        symlink(dir.path().join("Missing.mp3"), dir.path().join("Broken - Song.mp3")).expect("should link file");
+
+        let input = gather_audio_files(dir.path(), false).expect("should gather files");
+        assert_eq!(input.files, [dir.path().join("Linked - Song.mp3")]);
+
+        let input = gather_audio_files(dir.path(), true).expect("should gather files");
+        assert_eq!(
+            input.files,
+            [
+                dir.path().join("Linked - Song.mp3"),
+                dir.path().join("fast").join("Fast - Song.mp3")
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_input_weights() {
+        let input: InputSource = "core".parse().expect("should parse");
+        assert_eq!(input, InputSource::new(PathBuf::from("core")));
+        let input: InputSource = "extras@2.5".parse().expect("should parse");
+        assert!((input.weight - 2.5).abs() < f64::EPSILON);
+        let input: InputSource = "finals@must".parse().expect("should parse");
+        assert!(input.must);
+        let input: InputSource = "me@home".parse().expect("should parse");
+        assert_eq!(input.path, PathBuf::from("me@home"));
+        assert!("extras@0".parse::<InputSource>().is_err());
+    }
+
+    #[test]
+    fn picks_must_include_tracks_and_weighted_tracks() {
+        let inputs = [
+            "/core@must".parse().expect("should parse"),
+            "/extras@3".parse().expect("should parse"),
+            InputSource::new(PathBuf::from("/rare")),
+        ];
+        let files: Vec<PathBuf> = ["/core/1.mp3", "/extras/1.mp3", "/extras/2.mp3", "/rare/1.mp3"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        let pool = InputFiles {
+            files: files.clone(),
+            inputs: inputs.to_vec(),
+            ..InputFiles::default()
+        };
+        assert!(Selection::new(&pool, 5).is_err());
+
+        let selection = Selection::new(&pool, 2).expect("should create selection");
+        let mut rng = <StdRng as rand::SeedableRng>::seed_from_u64(1);
+        let mut rare = 0;
+        for _ in 0..600 {
+            let picked = selection.pick(&mut rng);
+            assert_eq!(picked.len(), 2);
+            assert_eq!(picked[0], files[0]);
+            rare += usize::from(picked[1] == files[3]);
+        }
+        // The rare track has a weight of 1 against 3 + 3 for the extras
+        assert!((50..150).contains(&rare), "{rare}");
+    }
+
+    #[test]
+    fn names_files_with_same_name_by_folder() {
+        let files: Vec<PathBuf> = [
+            "/pool/core/slow/Artist - Song.mp3",
+            "/pool/extras/slow/Artist - Song.mp3",
+            "/pool/extras/Other - Song.mp3",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+        let stems = distinct_stems(&files);
+        assert_eq!(stems.len(), 2);
+        assert_eq!(stems[&files[0]], "Artist - Song (core)");
+        assert_eq!(stems[&files[1]], "Artist - Song (extras)");
+    }
+
+    #[test]
+    fn gathers_playlist_entries_with_names() {
+        let dir = tempfile::tempdir().expect("should create temp dir");
+        let root = dunce::canonicalize(dir.path()).expect("should canonicalize");
+        fs::create_dir(root.join("music")).expect("should create dir");
+        let song = write(&root.join("music"), "01 Track.mp3", b"audio");
+        write(&root.join("music"), "Notes.txt", b"notes");
+        let playlist = write(
+            &root,
+            "list.m3u",
+            b"#EXTM3U\n#EXTINF:200,Artist - Song\nmusic/01 Track.mp3\nmusic/Notes.txt\nmusic/Missing.mp3\n",
+        );
+
+        let input =
+            gather_inputs(&[InputSource::new(playlist)], false, &Filter::default()).expect("should gather files");
+        assert_eq!(input.files, std::slice::from_ref(&song));
+        let name = &input.names[&song];
+        assert_eq!(name.artist.as_deref(), Some("Artist"));
+        assert_eq!(name.title, "Song");
+        assert_eq!(input.source_of(&song).map(InputSource::root), Some(root.as_path()));
+    }
+}
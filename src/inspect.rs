@@ -0,0 +1,253 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use clap::ValueEnum;
+use colored::Colorize;
+use rayon::prelude::*;
+use serde::Serialize;
+
+use crate::filter::Filter;
+use crate::input::{self, InputSource};
+use crate::tags;
+use crate::utils::format_duration;
+
+/// Lossy files below this bitrate in kbps are flagged.
+const MIN_LOSSY_BITRATE: u32 = 192;
+
+/// Sample rates that are not flagged as unusual.
+const COMMON_SAMPLE_RATES: [u32; 4] = [44100, 48000, 88200, 96000];
+
+/// Tracks shorter than this in seconds are flagged.
+const MIN_DURATION: f64 = 60.0;
+
+/// Tracks longer than this in seconds are flagged.
+const MAX_DURATION: f64 = 600.0;
+
+/// Output format for the inventory report.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    #[default]
+    Table,
+    Json,
+}
+
+/// Format and quality information for one input file.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct TrackInfo {
+    pub path: PathBuf,
+    /// Duration in seconds
+    pub duration: f64,
+    pub codec: Option<String>,
+    pub sample_rate: Option<u32>,
+    /// Bit depth of lossless files
+    pub bit_depth: Option<u8>,
+    /// Audio bitrate in kbps
+    pub bitrate: Option<u32>,
+    pub channels: Option<u8>,
+    pub artist: Option<String>,
+    pub title: Option<String>,
+    /// Outliers and read errors
+    pub issues: Vec<String>,
+}
+
+impl TrackInfo {
+    /// Read the file headers and tags and flag outliers.
+    ///
+    /// Files that cannot be read are listed with the error as their only issue.
+    pub fn read(path: &Path) -> Self {
+        let info = match tags::read_file_info(path) {
+            Ok(info) => info,
+            Err(error) => {
+                return Self {
+                    path: path.to_path_buf(),
+                    issues: vec![format!("{error:#}")],
+                    ..Self::default()
+                };
+            }
+        };
+        let properties = info.properties;
+        let mut track = Self {
+            path: path.to_path_buf(),
+            duration: properties.duration().as_secs_f64(),
+            codec: Some(info.codec),
+            sample_rate: properties.sample_rate(),
+            bit_depth: properties.bit_depth(),
+            bitrate: properties.audio_bitrate(),
+            channels: properties.channels(),
+            artist: info.artist,
+            title: info.title,
+            issues: Vec::new(),
+        };
+        track.issues = track.find_issues();
+        track
+    }
+
+    /// Returns true for lossy files without a bit depth, such as MP3 and AAC.
+    const fn is_lossy(&self) -> bool {
+        self.bit_depth.is_none()
+    }
+
+    fn find_issues(&self) -> Vec<String> {
+        let mut issues = Vec::new();
+        if let Some(bitrate) = self.bitrate
+            && self.is_lossy()
+            && bitrate < MIN_LOSSY_BITRATE
+        {
+            issues.push(format!("low bitrate {bitrate} kbps"));
+        }
+        if self.channels == Some(1) {
+            issues.push("mono".to_string());
+        }
+        if let Some(sample_rate) = self.sample_rate
+            && !COMMON_SAMPLE_RATES.contains(&sample_rate)
+        {
+            issues.push(format!("unusual sample rate {sample_rate} Hz"));
+        }
+        if self.duration < MIN_DURATION {
+            issues.push(format!("short {}", format_duration(self.duration)));
+        } else if self.duration > MAX_DURATION {
+            issues.push(format!("long {}", format_duration(self.duration)));
+        }
+        issues
+    }
+
+    /// Bit depth for lossless files and bitrate for lossy files.
+    fn quality(&self) -> String {
+        match (self.bit_depth, self.bitrate) {
+            (Some(bit_depth), _) => format!("{bit_depth} bit"),
+            (None, Some(bitrate)) => format!("{bitrate} kbps"),
+            (None, None) => String::new(),
+        }
+    }
+
+    fn file_name(&self) -> String {
+        self.path.file_name().unwrap_or_default().to_string_lossy().to_string()
+    }
+}
+
+/// Print the format and quality of every audio file in the input directory.
+pub fn inspect(input_path: &Path, recursive: bool, format: ReportFormat) -> Result<()> {
+    let files = input::gather_inputs(
+        &[InputSource::new(input_path.to_path_buf())],
+        recursive,
+        &Filter::default(),
+    )?
+    .files;
+    if files.is_empty() {
+        anyhow::bail!("No audio files found in: '{}'", input_path.display());
+    }
+    let tracks: Vec<TrackInfo> = files.par_iter().map(|file| TrackInfo::read(file)).collect();
+    match format {
+        ReportFormat::Table => print_table(&tracks),
+        ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&tracks)?),
+    }
+    Ok(())
+}
+
+/// Print the tracks as an aligned table with the issues highlighted.
+fn print_table(tracks: &[TrackInfo]) {
+    let header = ["File", "Duration", "Codec", "Rate", "Quality", "Ch", "Artist", "Title"];
+    let rows: Vec<[String; 8]> = tracks
+        .iter()
+        .map(|track| {
+            [
+                track.file_name(),
+                format_duration(track.duration),
+                track.codec.clone().unwrap_or_default(),
+                track.sample_rate.map(|rate| rate.to_string()).unwrap_or_default(),
+                track.quality(),
+                track.channels.map(|channels| channels.to_string()).unwrap_or_default(),
+                track.artist.clone().unwrap_or_default(),
+                track.title.clone().unwrap_or_default(),
+            ]
+        })
+        .collect();
+
+    let mut widths = header.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let format_row = |cells: &[String]| {
+        cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect::<Vec<_>>()
+            .join("  ")
+    };
+
+    println!("{}", format_row(&header.map(String::from)).bold());
+    for (track, row) in tracks.iter().zip(&rows) {
+        let line = format_row(row);
+        if track.issues.is_empty() {
+            println!("{}", line.trim_end());
+        } else {
+            println!("{line}  {}", track.issues.join(", ").yellow());
+        }
+    }
+
+    let total: f64 = tracks.iter().map(|track| track.duration).sum();
+    let flagged = tracks.iter().filter(|track| !track.issues.is_empty()).count();
+    println!(
+        "\n{} files, total duration {}, {flagged} flagged",
+        tracks.len(),
+        format_duration(total)
+    );
+}
+
+#[cfg(test)]
+mod inspect_tests {
+    use super::*;
+
+    fn track() -> TrackInfo {
+        TrackInfo {
+            path: PathBuf::from("Artist - Song.flac"),
+            duration: 180.0,
+            codec: Some("FLAC".to_string()),
+            sample_rate: Some(44100),
+            bit_depth: Some(16),
+            bitrate: Some(900),
+            channels: Some(2),
+            ..TrackInfo::default()
+        }
+    }
+
+    #[test]
+    fn flags_nothing_for_regular_track() {
+        assert!(track().find_issues().is_empty());
+    }
+
+    #[test]
+    fn flags_outliers() {
+        let track = TrackInfo {
+            duration: 45.0,
+            codec: Some("MP3".to_string()),
+            sample_rate: Some(22050),
+            bit_depth: None,
+            bitrate: Some(128),
+            channels: Some(1),
+            ..track()
+        };
+        assert_eq!(
+            track.find_issues(),
+            [
+                "low bitrate 128 kbps",
+                "mono",
+                "unusual sample rate 22050 Hz",
+                "short 0:45"
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_unreadable_file() {
+        let dir = tempfile::tempdir().expect("should create temp dir");
+        let path = dir.path().join("Broken.mp3");
+        std::fs::write(&path, b"not audio").expect("should write file");
+        let track = TrackInfo::read(&path);
+        assert!(track.codec.is_none());
+        assert_eq!(track.issues.len(), 1);
+    }
+}
@@ -0,0 +1,184 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use colored::Colorize;
+use rayon::prelude::*;
+
+use crate::audio::{self, DecodeCheck};
+use crate::tags;
+use crate::utils::format_duration;
+
+/// Decoded audio may be this much shorter in seconds than the header duration.
+const MAX_MISSING_DURATION: f64 = 1.0;
+
+/// Input file that cannot be played back reliably.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BrokenFile {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+/// Find input files that cannot be opened, have zero duration, fail to decode or are truncated.
+///
+/// All files are probed from their headers.
+/// With `full_decode`, every file is also decoded completely to find corrupt audio data.
+pub fn find_broken_files(files: &[PathBuf], full_decode: bool) -> Vec<BrokenFile> {
+    files
+        .par_iter()
+        .filter_map(|file| {
+            check_file(file, full_decode).map(|reason| BrokenFile {
+                path: file.clone(),
+                reason,
+            })
+        })
+        .collect()
+}
+
+/// Report broken input files and leave them out if requested.
+///
+/// # Errors
+/// Returns an error if there are broken files and `exclude` is not set.
+pub fn check_input_files(files: Vec<PathBuf>, full_decode: bool, exclude: bool) -> Result<Vec<PathBuf>> {
+    let broken = find_broken_files(&files, full_decode);
+    exclude_broken_files(files, &broken, exclude)
+}
+
+/// Report the given broken files and leave them out if requested.
+///
+/// # Errors
+/// Returns an error if there are broken files and `exclude` is not set.
+pub fn exclude_broken_files(files: Vec<PathBuf>, broken: &[BrokenFile], exclude: bool) -> Result<Vec<PathBuf>> {
+    if broken.is_empty() {
+        return Ok(files);
+    }
+    for file in broken {
+        let name = file.path.file_name().unwrap_or_default().to_string_lossy();
+        let message = format!("Broken file: {name}: {}", file.reason);
+        if exclude {
+            eprintln!("{}", message.yellow());
+        } else {
+            eprintln!("{}", message.red());
+        }
+    }
+    if !exclude {
+        anyhow::bail!(
+            "{} broken input file(s), use --skip-broken to leave them out",
+            broken.len()
+        );
+    }
+    eprintln!("{}\n", format!("Skipping {} broken file(s)", broken.len()).yellow());
+    Ok(files
+        .into_iter()
+        .filter(|file| !broken.iter().any(|broken| &broken.path == file))
+        .collect())
+}
+
+/// Returns the reason the file is broken, or `None` for a playable file.
+fn check_file(path: &Path, full_decode: bool) -> Option<String> {
+    let expected = match header_duration(path) {
+        Ok(expected) => expected,
+        Err(reason) => return Some(reason),
+    };
+    if !full_decode {
+        return None;
+    }
+    decode_problem(expected, audio::verify_file(path).as_ref())
+}
+
+/// Returns the reason the file is broken using the result of an earlier full decode,
+/// or `None` for a playable file.
+pub fn check_decoded_file(path: &Path, check: Result<&DecodeCheck, &anyhow::Error>) -> Option<String> {
+    match header_duration(path) {
+        Ok(expected) => decode_problem(expected, check),
+        Err(reason) => Some(reason),
+    }
+}
+
+/// Returns the duration in seconds from the file header, or the reason the file is broken.
+fn header_duration(path: &Path) -> Result<f64, String> {
+    let properties =
+        tags::read_properties(path).map_err(|error| format!("not a readable audio file ({})", error.root_cause()))?;
+    if properties.duration().is_zero() {
+        return Err("zero duration".to_string());
+    }
+    Ok(properties.duration().as_secs_f64())
+}
+
+/// Returns the reason the decode result shows a broken file, or `None` if it decoded completely.
+fn decode_problem(expected: f64, check: Result<&DecodeCheck, &anyhow::Error>) -> Option<String> {
+    match check {
+        Err(error) => Some(format!("failed to decode ({})", error.root_cause())),
+        Ok(check) if check.duration <= 0.0 => Some("no decodable audio".to_string()),
+        Ok(check) if check.decode_errors > 0 => Some(format!("{} decode error(s)", check.decode_errors)),
+        Ok(check) if expected - check.duration > MAX_MISSING_DURATION => Some(format!(
+            "truncated, decodes to {} of {}",
+            format_duration(check.duration),
+            format_duration(expected)
+        )),
+        Ok(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod integrity_tests {
+    use std::fs;
+
+    use super::*;
+    use crate::audio::{AudioBuffer, AudioFormat};
+
+    fn write_wav(path: &Path, frames: usize) {
+        let audio = AudioBuffer {
+            sample_rate: 44100,
+            channels: 2,
+            samples: vec![0.1; frames * 2],
+        };
+        audio::write_audio_file(path, &audio, AudioFormat::Wav, 16).expect("should write audio");
+    }
+
+    #[test]
+    fn finds_renamed_and_empty_files() {
+        let dir = tempfile::tempdir().expect("should create temp dir");
+        let good = dir.path().join("Good.wav");
+        let renamed = dir.path().join("Notes.mp3");
+        let empty = dir.path().join("Empty.wav");
+        write_wav(&good, 44100);
+        write_wav(&empty, 0);
+        fs::write(&renamed, "not audio").expect("should write file");
+
+        let files = vec![empty.clone(), good, renamed.clone()];
+        let broken = find_broken_files(&files, true);
+        let paths: Vec<&PathBuf> = broken.iter().map(|file| &file.path).collect();
+        assert_eq!(paths, [&empty, &renamed]);
+    }
+
+    #[test]
+    fn finds_truncated_file_with_full_decode() {
+        let dir = tempfile::tempdir().expect("should create temp dir");
+        let path = dir.path().join("Truncated.flac");
+        let audio = AudioBuffer {
+            sample_rate: 44100,
+            channels: 2,
+            samples: (0..88200).map(|index| if index % 7 < 3 { 0.4 } else { -0.3 }).collect(),
+        };
+        audio::write_audio_file(&path, &audio, AudioFormat::Flac, 16).expect("should write audio");
+        let contents = fs::read(&path).expect("should read file");
+        fs::write(&path, &contents[..contents.len() / 2]).expect("should write file");
+
+        let files = vec![path];
+        assert!(find_broken_files(&files, false).is_empty());
+        assert_eq!(find_broken_files(&files, true).len(), 1);
+    }
+
+    #[test]
+    fn excludes_broken_files_when_requested() {
+        let dir = tempfile::tempdir().expect("should create temp dir");
+        let good = dir.path().join("Good.wav");
+        let renamed = dir.path().join("Notes.wav");
+        write_wav(&good, 44100);
+        fs::write(&renamed, "not audio").expect("should write file");
+
+        let files = vec![good.clone(), renamed];
+        assert!(check_input_files(files.clone(), false, false).is_err());
+        assert_eq!(check_input_files(files, false, true).expect("should skip"), [good]);
+    }
+}
@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use anyhow::{Context, Result, anyhow};
+use ebur128::{EbuR128, Mode};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::audio::{self, AudioBuffer};
+
+/// Quietest integrated loudness EBU R128 can measure, used for silent files.
+const SILENCE_LUFS: f64 = -70.0;
+
+/// Normalized copies are limited so the true peak stays below this level.
+pub const MAX_TRUE_PEAK_DBTP: f64 = -1.0;
+
+/// Cache file name inside the user cache directory.
+const CACHE_FILE_NAME: &str = "loudness.json";
+
+/// EBU R128 loudness measurement for one input file.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Loudness {
+    /// Integrated loudness in LUFS
+    pub integrated: f64,
+    /// Highest true peak of all channels in dBTP
+    pub true_peak: f64,
+}
+
+impl Loudness {
+    /// Gain in dB that brings the file to the target loudness.
+    ///
+    /// The gain is limited so the true peak stays below [`MAX_TRUE_PEAK_DBTP`].
+    /// Silent files are left as they are.
+    pub fn normalization_gain(&self, target: f64) -> f64 {
+        if self.integrated <= SILENCE_LUFS {
+            return 0.0;
+        }
+        (target - self.integrated).min(MAX_TRUE_PEAK_DBTP - self.true_peak)
+    }
+}
+
+/// Measured loudness values keyed by absolute input path.
+///
+/// Entries are reused as long as the file size and modification time stay the same.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct LoudnessCache {
+    entries: HashMap<PathBuf, CacheEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    size: u64,
+    modified_nanos: u64,
+    loudness: Loudness,
+}
+
+impl LoudnessCache {
+    /// Load the cache file, starting with an empty cache if it is missing or unreadable.
+    fn load(path: &Path) -> Self {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context(format!("Failed to create cache directory: {}", parent.display()))?;
+        }
+        let contents = serde_json::to_string_pretty(self)?;
+        fs::write(path, contents).context(format!("Failed to write loudness cache: {}", path.display()))
+    }
+
+    fn get(&self, path: &Path, size: u64, modified_nanos: u64) -> Option<Loudness> {
+        self.entries
+            .get(path)
+            .filter(|entry| entry.size == size && entry.modified_nanos == modified_nanos)
+            .map(|entry| entry.loudness)
+    }
+}
+
+/// Default location of the loudness cache file.
+pub fn default_cache_path() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join(env!("CARGO_PKG_NAME")).join(CACHE_FILE_NAME))
+}
+
+/// Measure the loudness of all files, reusing cached measurements of unchanged files.
+///
+/// Files missing from the cache use the given measurements from an earlier decode if there is one,
+/// otherwise they are measured in parallel. New measurements are written back to the cache file.
+pub fn measure_files(
+    files: &[PathBuf],
+    cache_path: Option<&Path>,
+    measured: &HashMap<PathBuf, Loudness>,
+) -> Result<HashMap<PathBuf, Loudness>> {
+    let mut cache = cache_path.map(LoudnessCache::load).unwrap_or_default();
+
+    let mut results = HashMap::new();
+    let mut missing = Vec::new();
+    for file in files {
+        let (size, modified_nanos) = file_stamp(file)?;
+        match cache.get(file, size, modified_nanos) {
+            Some(loudness) => {
+                results.insert(file.clone(), loudness);
+            }
+            None => missing.push((file, size, modified_nanos)),
+        }
+    }
+
+    let measured: Vec<(PathBuf, CacheEntry)> = missing
+        .par_iter()
+        .map(|&(file, size, modified_nanos)| {
+            let loudness = match measured.get(file) {
+                Some(&loudness) => loudness,
+                None => measure_file(file)?,
+            };
+            Ok((
+                file.clone(),
+                CacheEntry {
+                    size,
+                    modified_nanos,
+                    loudness,
+                },
+            ))
+        })
+        .collect::<Result<_>>()?;
+
+    if !measured.is_empty() {
+        for (file, entry) in measured {
+            results.insert(file.clone(), entry.loudness);
+            cache.entries.insert(file, entry);
+        }
+        if let Some(path) = cache_path {
+            cache.save(path)?;
+        }
+    }
+    Ok(results)
+}
+
+/// Decode the file and measure its integrated loudness and true peak.
+pub fn measure_file(path: &Path) -> Result<Loudness> {
+    let audio = audio::decode_file(path)?;
+    measure(&audio).context(format!("Failed to measure loudness: {}", path.display()))
+}
+
+/// Measure integrated loudness and true peak of decoded audio.
+pub fn measure(audio: &AudioBuffer) -> Result<Loudness> {
+    let channels = u32::try_from(audio.channels)?;
+    let mut meter = EbuR128::new(channels, audio.sample_rate, Mode::I | Mode::TRUE_PEAK)
+        .map_err(|error| anyhow!("Failed to create loudness meter: {error}"))?;
+    meter
+        .add_frames_f32(&audio.samples)
+        .map_err(|error| anyhow!("Failed to analyse audio: {error}"))?;
+
+    let integrated = meter
+        .loudness_global()
+        .map_err(|error| anyhow!("Failed to get integrated loudness: {error}"))?;
+    let mut peak: f64 = 0.0;
+    for channel in 0..channels {
+        let channel_peak = meter
+            .true_peak(channel)
+            .map_err(|error| anyhow!("Failed to get true peak: {error}"))?;
+        peak = peak.max(channel_peak);
+    }
+
+    Ok(Loudness {
+        integrated: if integrated.is_finite() {
+            integrated.max(SILENCE_LUFS)
+        } else {
+            SILENCE_LUFS
+        },
+        true_peak: if peak > 0.0 { 20.0 * peak.log10() } else { SILENCE_LUFS },
+    })
+}
+
+/// File size and modification time used to detect changed files.
+fn file_stamp(path: &Path) -> Result<(u64, u64)> {
+    let metadata = fs::metadata(path).context(format!("Failed to read file metadata: {}", path.display()))?;
+    let modified = metadata
+        .modified()
+        .context(format!("Failed to read modification time: {}", path.display()))?;
+    #[allow(clippy::cast_possible_truncation)]
+    let modified_nanos = modified
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_nanos() as u64);
+    Ok((metadata.len(), modified_nanos))
+}
+
+#[cfg(test)]
+mod loudness_tests {
+    use super::*;
+
+    fn sine(sample_rate: u32, seconds: usize, amplitude: f32) -> AudioBuffer {
+        #[allow(clippy::cast_precision_loss)]
+        let samples = (0..sample_rate as usize * seconds)
+            .flat_map(|frame| {
+                let value = (2.0 * std::f32::consts::PI * 997.0 * frame as f32 / sample_rate as f32).sin() * amplitude;
+                [value, value]
+            })
+            .collect();
+        AudioBuffer {
+            sample_rate,
+            channels: 2,
+            samples,
+        }
+    }
+
+    #[test]
+    fn measures_sine_loudness() {
+        // A 997 Hz stereo sine at -20 dBFS peak measures close to -20 LUFS
+        let loudness = measure(&sine(48000, 5, 0.1)).expect("should measure loudness");
+        assert!((loudness.integrated + 20.0).abs() < 0.5, "{loudness:?}");
+        assert!((loudness.true_peak + 20.0).abs() < 0.5, "{loudness:?}");
+    }
+
+    #[test]
+    fn limits_normalization_gain_by_true_peak() {
+        let quiet = Loudness {
+            integrated: -30.0,
+            true_peak: -12.0,
+        };
+        assert!((quiet.normalization_gain(-23.0) - 7.0).abs() < f64::EPSILON);
+        assert!((quiet.normalization_gain(-14.0) - 11.0).abs() < f64::EPSILON);
+        let silent = Loudness {
+            integrated: SILENCE_LUFS,
+            true_peak: SILENCE_LUFS,
+        };
+        assert!(silent.normalization_gain(-14.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn reuses_cached_measurements() {
+        let dir = tempfile::tempdir().expect("should create temp dir");
+        let file = dir.path().join("Artist - Song.wav");
+        audio::write_audio_file(&file, &sine(44100, 1, 0.5), audio::AudioFormat::Wav, 16).expect("should write audio");
+        let cache_path = dir.path().join("cache").join(CACHE_FILE_NAME);
+
+        let first =
+            measure_files(std::slice::from_ref(&file), Some(&cache_path), &HashMap::new()).expect("should measure");
+        assert!(cache_path.is_file());
+
+        // Replace the cached value to check it is used instead of measuring again
+        let mut cache = LoudnessCache::load(&cache_path);
+        let entry = cache.entries.get_mut(&file).expect("should have cache entry");
+        entry.loudness.integrated = -42.0;
+        cache.save(&cache_path).expect("should save cache");
+
+        let second =
+            measure_files(std::slice::from_ref(&file), Some(&cache_path), &HashMap::new()).expect("should measure");
+        assert!((second[&file].integrated + 42.0).abs() < f64::EPSILON);
+        assert!((first[&file].integrated + 42.0).abs() > 1.0);
+    }
+}
@@ -1,7 +1,22 @@
+mod audio;
+mod category;
+mod config;
+mod cue;
+mod duplicates;
+mod filter;
+mod input;
+mod inspect;
+mod integrity;
+mod loudness;
+mod naming;
+mod playlist;
 mod randomizer;
+mod render;
+mod rules;
+mod tags;
 mod utils;
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use anyhow::Result;
 use clap::parser::ValueSource;
@@ -9,16 +24,31 @@ use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
 use clap_complete::Shell;
 use colored::Colorize;
 
+use crate::audio::{AudioFormat, BitDepth};
+use crate::category::{CategoryOrder, CategorySource};
+use crate::config::Config;
+use crate::input::InputSource;
+use crate::inspect::ReportFormat;
+
 #[derive(Parser)]
+#[allow(clippy::struct_excessive_bools)]
 #[command(author, about, version, arg_required_else_help = true, name = env!("CARGO_BIN_NAME"))]
 struct Args {
     #[command(subcommand)]
     command: Option<CliCommand>,
 
-    /// Input directory with audio files to randomize
+    /// Input directory or M3U, M3U8 or PLS playlist with audio files to randomize
     #[arg(value_hint = clap::ValueHint::DirPath)]
     input_dir: Option<PathBuf>,
 
+    /// Additional input directory or playlist, optionally with a weight or must include flag: DIR[@WEIGHT|@must]
+    #[arg(short, long = "input", value_name = "DIR", value_hint = clap::ValueHint::DirPath)]
+    inputs: Vec<InputSource>,
+
+    /// Number of tracks in each permutation, picked from the inputs by weight (default is all tracks)
+    #[arg(long, value_name = "NUM", conflicts_with_all = ["category_order", "pattern"])]
+    tracks: Option<usize>,
+
     /// Number of randomized orders to generate
     #[arg(short, long, value_name = "NUM", default_value_t = 1)]
     permutations: usize,
@@ -35,6 +65,199 @@ struct Args {
     #[arg(short, long)]
     force: bool,
 
+    /// Event or category name used in output names
+    #[arg(short, long, value_name = "NAME", default_value = naming::DEFAULT_EVENT)]
+    event: String,
+
+    /// Include audio files from subfolders of the input directory
+    #[arg(short = 'R', long)]
+    recursive: bool,
+
+    /// Where track categories come from: top-level subfolders, genre tags or a categories.toml file [default: folder]
+    #[arg(long, value_enum, value_name = "SOURCE")]
+    categories: Option<CategorySource>,
+
+    /// Sidecar TOML file that lists the tracks of each category
+    #[arg(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath, conflicts_with = "categories")]
+    category_file: Option<PathBuf>,
+
+    /// Order all tracks by category
+    #[arg(long, value_enum, value_name = "ORDER")]
+    category_order: Option<CategoryOrder>,
+
+    /// Fill each slot with a random track from the named category, for example "slow,fast,medium"
+    #[arg(long, value_name = "CATEGORIES", conflicts_with = "category_order")]
+    pattern: Option<String>,
+
+    /// Only use input files whose name or relative path matches the glob, or regex with a "re:" prefix
+    #[arg(long, value_name = "PATTERN")]
+    include: Vec<String>,
+
+    /// Leave out input files whose name or relative path matches the glob, or regex with a "re:" prefix
+    #[arg(long, value_name = "PATTERN")]
+    exclude: Vec<String>,
+
+    /// Text file of banned tracks, one per line, and artists with an "artist:" prefix
+    #[arg(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    exclude_list: Option<PathBuf>,
+
+    /// Decode every input file completely to find corrupt audio data before generating
+    #[arg(long)]
+    verify: bool,
+
+    /// Leave out input files that cannot be opened or decoded instead of stopping
+    #[arg(long)]
+    skip_broken: bool,
+
+    /// Find duplicate tracks by file contents and audio fingerprint and report them
+    #[arg(long)]
+    duplicates: bool,
+
+    /// Keep only the best quality file of each group of duplicate tracks
+    #[arg(long)]
+    keep_best: bool,
+
+    /// Competition rules TOML file to validate the input files against before generating
+    #[arg(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    rules: Option<PathBuf>,
+
+    /// Output folder name template. Placeholders: {event} {number}
+    #[arg(long = "folder-name", value_name = "TEMPLATE", default_value = naming::DEFAULT_FOLDER_TEMPLATE)]
+    folder_template: String,
+
+    /// Output file name template. Placeholders: {event} {number} {slot} {artist} {title} {stem} {ext}
+    /// (default is "{slot} FDO impro - {stem}.{ext}", or "{slot} {event} - {stem}.{ext}" with --event)
+    #[arg(long = "file-name", value_name = "TEMPLATE")]
+    file_template: Option<String>,
+
+    /// Sanitise and shorten output names for FAT formatted USB sticks and hardware players
+    #[arg(long)]
+    portable_names: bool,
+
+    /// Trim each track to an excerpt of the given length in seconds
+    #[arg(long, value_name = "SECONDS")]
+    excerpt: Option<f64>,
+
+    /// Excerpt start position in seconds, unless a sidecar CUE file gives the start
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 0.0,
+        requires = "excerpt",
+        conflicts_with = "random_start"
+    )]
+    excerpt_start: f64,
+
+    /// Start each excerpt at a random position between the skipped intro and outro, drawn for each permutation
+    #[arg(long, requires = "excerpt")]
+    random_start: bool,
+
+    /// Intro length in seconds to skip with random excerpt starts
+    #[arg(long, value_name = "SECONDS", default_value_t = 20.0, requires = "random_start")]
+    skip_intro: f64,
+
+    /// Outro length in seconds to skip with random excerpt starts
+    #[arg(long, value_name = "SECONDS", default_value_t = 20.0, requires = "random_start")]
+    skip_outro: f64,
+
+    /// Excerpt fade-in length in seconds
+    #[arg(long, value_name = "SECONDS", default_value_t = 0.5, requires = "excerpt")]
+    fade_in: f64,
+
+    /// Excerpt fade-out length in seconds
+    #[arg(long, value_name = "SECONDS", default_value_t = 3.0, requires = "excerpt")]
+    fade_out: f64,
+
+    /// Join each permutation into one continuous audio file
+    #[arg(long, conflicts_with_all = ["tags", "player_order"])]
+    combine: bool,
+
+    /// Silence between tracks in seconds for combined files
+    #[arg(long, value_name = "SECONDS", default_value_t = 2.0, requires = "combine")]
+    gap: f64,
+
+    /// Mix combined files continuously with equal-power crossfades of the given length in seconds
+    #[arg(long, value_name = "SECONDS", requires = "combine", conflicts_with = "gap")]
+    crossfade: Option<f64>,
+
+    /// Match the levels of the tracks in combined files
+    #[arg(long, requires = "combine")]
+    match_gain: bool,
+
+    /// Detect leading and trailing silence and warn about tracks that have it
+    #[arg(long)]
+    detect_silence: bool,
+
+    /// Trim leading and trailing silence in the output copies
+    #[arg(long)]
+    trim_silence: bool,
+
+    /// Level in dBFS below which audio counts as silence
+    #[arg(long, value_name = "DB", default_value_t = -50.0, allow_negative_numbers = true)]
+    silence_threshold: f64,
+
+    /// Minimum length in seconds for silence to be detected
+    #[arg(long, value_name = "SECONDS", default_value_t = 0.5)]
+    min_silence: f64,
+
+    /// Print integrated loudness and true peak of each input file
+    #[arg(long)]
+    loudness: bool,
+
+    /// Write copies normalized to the given integrated loudness in LUFS, for example -16
+    #[arg(long, value_name = "LUFS", allow_negative_numbers = true)]
+    normalize: Option<f64>,
+
+    /// Audio format for rendered files
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        default_value_t,
+        conflicts_with = "convert_to"
+    )]
+    render_format: AudioFormat,
+
+    /// Convert all output files to one format, including files that are not rendered
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    convert_to: Option<AudioFormat>,
+
+    /// Sample rate in Hz for converted and combined files (default is highest input sample rate)
+    #[arg(long, value_name = "HZ")]
+    sample_rate: Option<u32>,
+
+    /// Bit depth for rendered and converted files
+    #[arg(long, value_enum, value_name = "BITS", default_value_t)]
+    bit_depth: BitDepth,
+
+    /// Write track number, album and album artist tags into the output files
+    #[arg(short, long)]
+    tags: bool,
+
+    /// Set file modification times in playback order for hardware players
+    #[arg(long)]
+    player_order: bool,
+
+    /// Flush each output file to disk before writing the next one
+    #[arg(long, requires = "player_order")]
+    fsync: bool,
+
+    /// Hard link the input files into the output folders instead of copying them
+    #[arg(long, conflicts_with_all = ["tags", "player_order", "excerpt", "combine", "trim_silence", "normalize", "render_format", "convert_to"])]
+    link: bool,
+
+    /// Write an M3U8 playlist of the input files for each permutation instead of copying them
+    #[arg(long, conflicts_with_all = ["link", "tags", "player_order", "excerpt", "combine", "trim_silence", "normalize", "render_format", "convert_to"])]
+    playlist_only: bool,
+
+    /// Random seed to reproduce the orders and excerpt starts of a previous run
+    #[arg(long, value_name = "NUM")]
+    seed: Option<u64>,
+
+    /// Number of permutations to copy concurrently (default is number of CPUs). Combined files are written one at a time
+    #[arg(short, long, value_name = "NUM", conflicts_with = "combine")]
+    jobs: Option<usize>,
+
     /// Verbose output
     #[arg(short, long, global = true)]
     verbose: bool,
@@ -53,6 +276,36 @@ enum CliCommand {
         #[arg(short = 'I', long)]
         install: bool,
     },
+    /// List the format, duration and tags of each audio file and flag outliers
+    #[command(name = "inspect")]
+    Inspect {
+        /// Input directory or playlist with audio files to inspect
+        #[arg(value_hint = clap::ValueHint::DirPath)]
+        input_dir: Option<PathBuf>,
+
+        /// Include audio files from subfolders
+        #[arg(short = 'R', long)]
+        recursive: bool,
+
+        /// Report output format
+        #[arg(long, value_enum, default_value_t)]
+        format: ReportFormat,
+    },
+    /// Check each audio file against the competition rules file
+    #[command(name = "validate")]
+    Validate {
+        /// Input directory or playlist with audio files to validate
+        #[arg(value_hint = clap::ValueHint::DirPath)]
+        input_dir: Option<PathBuf>,
+
+        /// Include audio files from subfolders
+        #[arg(short = 'R', long)]
+        recursive: bool,
+
+        /// TOML file with the rules
+        #[arg(short, long, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+        rules: PathBuf,
+    },
 }
 
 fn main() -> Result<()> {
@@ -71,8 +324,27 @@ fn main() -> Result<()> {
         );
     }
 
-    let absolute_input_path = utils::resolve_input_path(args.input_dir.as_deref())?;
-    let absolute_output_root = utils::resolve_output_root(args.output_path.as_deref(), &absolute_input_path)?;
+    if let Some(CliCommand::Inspect {
+        input_dir,
+        recursive,
+        format,
+    }) = &args.command
+    {
+        let absolute_input_path = utils::resolve_input_path(input_dir.as_deref())?;
+        return inspect::inspect(&absolute_input_path, *recursive, *format);
+    }
+    if let Some(CliCommand::Validate {
+        input_dir,
+        recursive,
+        rules,
+    }) = &args.command
+    {
+        let absolute_input_path = utils::resolve_input_path(input_dir.as_deref())?;
+        return rules::validate(&absolute_input_path, rules, *recursive);
+    }
+
+    let inputs = resolve_inputs(args.input_dir.as_deref(), &args.inputs)?;
+    let absolute_output_root = utils::resolve_output_root(args.output_path.as_deref(), &inputs[0].path)?;
 
     let permutations = resolve_permutations(
         args.permutations,
@@ -80,13 +352,26 @@ fn main() -> Result<()> {
         permutations_value_source,
     )?;
 
-    randomizer::generate_unique_permutations(
-        &absolute_input_path,
-        absolute_output_root,
-        permutations,
-        args.verbose,
-        args.force,
-    )
+    let config = Config::from_args(&args, permutations)?;
+
+    randomizer::generate_unique_permutations(&inputs, absolute_output_root, &config)
+}
+
+/// Resolve the input directory or playlist and the additional inputs to absolute paths.
+///
+/// The current directory is used if no input is given.
+fn resolve_inputs(input_dir: Option<&Path>, inputs: &[InputSource]) -> Result<Vec<InputSource>> {
+    let mut resolved = Vec::new();
+    if input_dir.is_some() || inputs.is_empty() {
+        resolved.push(InputSource::new(utils::resolve_input_path(input_dir)?));
+    }
+    for input in inputs {
+        resolved.push(InputSource {
+            path: utils::resolve_input_path(Some(&input.path))?,
+            ..input.clone()
+        });
+    }
+    Ok(resolved)
 }
 
 /// Resolve the effective permutations count from the preferred flag and positional argument.
@@ -212,4 +497,88 @@ mod cli_args_tests {
 
         assert_eq!(permutations, 99);
     }
+
+    #[test]
+    fn parses_additional_inputs() {
+        let (args, _) = parse_args(&[
+            "fdo-randomizer",
+            "core",
+            "-i",
+            "extras@2",
+            "--input",
+            "finals@must",
+            "--tracks",
+            "10",
+        ]);
+        assert_eq!(args.input_dir, Some(PathBuf::from("core")));
+        assert_eq!(args.inputs.len(), 2);
+        assert!((args.inputs[0].weight - 2.0).abs() < f64::EPSILON);
+        assert!(args.inputs[1].must);
+        assert_eq!(args.tracks, Some(10));
+    }
+
+    #[test]
+    fn combines_one_permutation_at_a_time() {
+        let (args, _) = parse_args(&["fdo-randomizer", "music", "--combine"]);
+        let config = Config::from_args(&args, 1).expect("should create config");
+        assert_eq!(config.jobs, 1);
+
+        let result = Args::try_parse_from(["fdo-randomizer", "music", "--combine", "--jobs", "4"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn requires_recursive_for_folder_categories_of_directories_only() {
+        let config = |arguments: &[&str]| {
+            let (args, _) = parse_args(&[&["fdo-randomizer"], arguments, &["--category-order", "rotate"]].concat());
+            Config::from_args(&args, 1)
+        };
+        assert!(config(&["music"]).is_err());
+        assert!(config(&["music", "-R"]).is_ok());
+        assert!(config(&["set.m3u8"]).is_ok());
+        assert!(config(&["set.m3u8", "-i", "other.pls"]).is_ok());
+        assert!(config(&["set.m3u8", "-i", "music"]).is_err());
+    }
+
+    #[test]
+    fn renders_to_render_format_without_converting() {
+        let (args, _) = parse_args(&["fdo-randomizer", "music", "--excerpt", "60", "--render-format", "flac"]);
+        let config = Config::from_args(&args, 1).expect("should create config");
+        assert_eq!(config.output_format, AudioFormat::Flac);
+        assert!(!config.convert);
+
+        let result = Args::try_parse_from([
+            "fdo-randomizer",
+            "music",
+            "--render-format",
+            "flac",
+            "--convert-to",
+            "wav",
+        ]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn keeps_original_default_output_names() {
+        let (args, _) = parse_args(&["fdo-randomizer", "music"]);
+        let config = Config::from_args(&args, 1).expect("should create config");
+        let values = naming::NameValues {
+            event: &config.event,
+            number: 1,
+            number_width: 2,
+            slot: 1,
+            slot_width: 2,
+            stem: "Artist - Song",
+            ext: "mp3",
+            ..naming::NameValues::default()
+        };
+        assert_eq!(
+            config.folder_template.render(&values).expect("should render"),
+            "FDO Impro 01"
+        );
+        assert_eq!(
+            config.file_template.render(&values).expect("should render"),
+            "01 FDO impro - Artist - Song.mp3"
+        );
+    }
 }
@@ -0,0 +1,513 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use anyhow::{Context, Result, anyhow};
+
+use crate::utils;
+
+/// Default event name used in output names.
+pub const DEFAULT_EVENT: &str = "FDO Impro";
+
+/// Default template for the output directory of each permutation.
+pub const DEFAULT_FOLDER_TEMPLATE: &str = "{event} {number}";
+
+/// Default template for the numbered output files, keeping the original output names.
+pub const DEFAULT_FILE_TEMPLATE: &str = "{slot} FDO impro - {stem}.{ext}";
+
+/// Default template for the numbered output files with a custom event name.
+pub const EVENT_FILE_TEMPLATE: &str = "{slot} {event} - {stem}.{ext}";
+
+/// Returns the file name template to use when none is given for the event.
+pub fn default_file_template(event: &str) -> &'static str {
+    if event == DEFAULT_EVENT {
+        DEFAULT_FILE_TEMPLATE
+    } else {
+        EVENT_FILE_TEMPLATE
+    }
+}
+
+/// Maximum length of a generated name with portable names, including the extension.
+const PORTABLE_NAME_MAX_LENGTH: usize = 64;
+
+/// Device names that are reserved on FAT file systems.
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9", "LPT1", "LPT2",
+    "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Characters that are not allowed in file names on at least one of the supported platforms.
+const UNSAFE_CHARACTERS: [char; 9] = ['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// Value that can be inserted into a naming template.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placeholder {
+    /// Event or category name
+    Event,
+    /// Permutation number, zero-padded to the number of permutations
+    Number,
+    /// Playback position inside the permutation, zero-padded to the number of tracks
+    Slot,
+    /// Track artist
+    Artist,
+    /// Track title
+    Title,
+    /// Original file name without extension
+    Stem,
+    /// Original file extension without the leading dot
+    Ext,
+}
+
+impl Placeholder {
+    const ALL: [Self; 7] = [
+        Self::Event,
+        Self::Number,
+        Self::Slot,
+        Self::Artist,
+        Self::Title,
+        Self::Stem,
+        Self::Ext,
+    ];
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Event => "event",
+            Self::Number => "number",
+            Self::Slot => "slot",
+            Self::Artist => "artist",
+            Self::Title => "title",
+            Self::Stem => "stem",
+            Self::Ext => "ext",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|placeholder| placeholder.name() == name)
+    }
+
+    /// Returns true for free text values that can be shortened.
+    const fn is_text(self) -> bool {
+        matches!(self, Self::Event | Self::Artist | Self::Title | Self::Stem)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+/// Parsed naming template such as `{slot} {event} - {stem}.{ext}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameTemplate {
+    template: String,
+    segments: Vec<Segment>,
+    portable: bool,
+}
+
+/// Values for rendering a naming template.
+#[derive(Debug, Clone, Default)]
+pub struct NameValues<'a> {
+    pub event: &'a str,
+    pub number: usize,
+    pub number_width: usize,
+    pub slot: usize,
+    pub slot_width: usize,
+    pub artist: &'a str,
+    pub title: &'a str,
+    pub stem: &'a str,
+    pub ext: &'a str,
+}
+
+impl<'a> NameValues<'a> {
+    /// Create values for the given output file, with artist and title parsed from the file name.
+    pub fn for_file(path: &'a Path, event: &'a str) -> Result<Self> {
+        #[allow(clippy::unnecessary_debug_formatting)]
+        let stem = path
+            .file_stem()
+            .and_then(|os_str| os_str.to_str())
+            .ok_or_else(|| anyhow!("Invalid file name in path: {path:?}"))?;
+        let ext = path.extension().and_then(|os_str| os_str.to_str()).unwrap_or_default();
+        let (artist, title) = utils::parse_artist_and_title(stem);
+        Ok(Self {
+            event,
+            artist: artist.unwrap_or("Unknown Artist"),
+            title,
+            stem,
+            ext,
+            ..Self::default()
+        })
+    }
+}
+
+impl NameTemplate {
+    /// Parse a template for the output directory names.
+    ///
+    /// The template must contain the `{number}` placeholder so each permutation gets its own directory.
+    pub fn folder(template: &str) -> Result<Self> {
+        let parsed = Self::parse(template).context(format!("Invalid folder name template: '{template}'"))?;
+        if !parsed.contains(Placeholder::Number) {
+            anyhow::bail!("Folder name template must contain {{number}}: '{template}'");
+        }
+        if let Some(placeholder) = parsed
+            .placeholders()
+            .find(|p| !matches!(p, Placeholder::Event | Placeholder::Number))
+        {
+            anyhow::bail!(
+                "Folder name template can only use {{event}} and {{number}}, found {{{}}}",
+                placeholder.name()
+            );
+        }
+        Ok(parsed)
+    }
+
+    /// Parse a template for the output file names.
+    ///
+    /// Without the `{slot}` placeholder the names may collide, which is checked when the names are rendered.
+    pub fn file(template: &str) -> Result<Self> {
+        Self::parse(template).context(format!("Invalid file name template: '{template}'"))
+    }
+
+    fn parse(template: &str) -> Result<Self> {
+        let mut segments = Vec::new();
+        let mut rest = template;
+        while !rest.is_empty() {
+            if let Some(start) = rest.find(['{', '}']) {
+                if rest[start..].starts_with('}') {
+                    anyhow::bail!("Unmatched '}}' at position {}", template.len() - rest.len() + start);
+                }
+                if start > 0 {
+                    segments.push(Segment::Literal(rest[..start].to_string()));
+                }
+                let end = rest[start..]
+                    .find('}')
+                    .ok_or_else(|| anyhow!("Unclosed '{{' at position {}", template.len() - rest.len() + start))?;
+                let name = &rest[start + 1..start + end];
+                let placeholder = Placeholder::from_name(name).ok_or_else(|| {
+                    let names: Vec<String> = Placeholder::ALL.iter().map(|p| format!("{{{}}}", p.name())).collect();
+                    anyhow!("Unknown placeholder {{{name}}}, expected one of: {}", names.join(", "))
+                })?;
+                segments.push(Segment::Placeholder(placeholder));
+                rest = &rest[start + end + 1..];
+            } else {
+                segments.push(Segment::Literal(rest.to_string()));
+                rest = "";
+            }
+        }
+
+        for segment in &segments {
+            if let Segment::Literal(text) = segment
+                && let Some(character) = text.chars().find(|c| is_unsafe_character(*c))
+            {
+                anyhow::bail!("Template contains a character that is not allowed in file names: {character:?}");
+            }
+        }
+
+        Ok(Self {
+            template: template.to_string(),
+            segments,
+            portable: false,
+        })
+    }
+
+    /// Sanitise and shorten rendered names so they work on FAT file systems and hardware players.
+    #[must_use]
+    pub const fn portable(mut self, portable: bool) -> Self {
+        self.portable = portable;
+        self
+    }
+
+    fn contains(&self, placeholder: Placeholder) -> bool {
+        self.segments.contains(&Segment::Placeholder(placeholder))
+    }
+
+    fn placeholders(&self) -> impl Iterator<Item = Placeholder> + '_ {
+        self.segments.iter().filter_map(|segment| match segment {
+            Segment::Placeholder(placeholder) => Some(*placeholder),
+            Segment::Literal(_) => None,
+        })
+    }
+
+    /// Render the template with the given values.
+    ///
+    /// Returns an error if the result is not a valid file name.
+    pub fn render(&self, values: &NameValues) -> Result<String> {
+        let name: String = if self.portable {
+            self.render_portable(values)
+        } else {
+            self.segments
+                .iter()
+                .map(|segment| match segment {
+                    Segment::Literal(text) => text.clone(),
+                    Segment::Placeholder(placeholder) => render_placeholder(*placeholder, values),
+                })
+                .collect()
+        };
+
+        validate_file_name(&name).context(format!("Invalid name from template '{}'", self.template))?;
+        Ok(name)
+    }
+
+    /// Render a sanitised name that fits into the portable name length.
+    ///
+    /// Only the free text values get shortened,
+    /// so the numeric prefix and the file extension always stay intact.
+    fn render_portable(&self, values: &NameValues) -> String {
+        let mut parts: Vec<(String, bool)> = self
+            .segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(text) => (sanitize_portable(text), false),
+                Segment::Placeholder(placeholder) => (
+                    sanitize_portable(&render_placeholder(*placeholder, values)),
+                    placeholder.is_text(),
+                ),
+            })
+            .collect();
+
+        // Shorten the longest text value one character at a time until the name fits
+        while parts.iter().map(|(text, _)| text.chars().count()).sum::<usize>() > PORTABLE_NAME_MAX_LENGTH {
+            let Some((longest, _)) = parts
+                .iter_mut()
+                .filter(|(text, shorten)| *shorten && !text.is_empty())
+                .max_by_key(|(text, _)| text.chars().count())
+            else {
+                break;
+            };
+            longest.pop();
+            let trimmed_length = longest.trim_end_matches(['.', ' ']).len();
+            longest.truncate(trimmed_length);
+        }
+
+        let name: String = parts.into_iter().map(|(text, _)| text).collect();
+        let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+        let mut name = name
+            .trim_start_matches(['.', ' '])
+            .trim_end_matches(['.', ' '])
+            .to_string();
+
+        let base_name = name.split('.').next().unwrap_or_default();
+        if RESERVED_NAMES
+            .iter()
+            .any(|reserved| reserved.eq_ignore_ascii_case(base_name))
+        {
+            name.insert(0, '_');
+        }
+        name
+    }
+}
+
+impl fmt::Display for NameTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.template)
+    }
+}
+
+fn render_placeholder(placeholder: Placeholder, values: &NameValues) -> String {
+    match placeholder {
+        Placeholder::Event => values.event.to_string(),
+        Placeholder::Number => format!("{:0width$}", values.number, width = values.number_width),
+        Placeholder::Slot => format!("{:0width$}", values.slot, width = values.slot_width),
+        Placeholder::Artist => values.artist.to_string(),
+        Placeholder::Title => values.title.to_string(),
+        Placeholder::Stem => values.stem.to_string(),
+        Placeholder::Ext => values.ext.to_string(),
+    }
+}
+
+/// Check that the event name can be used inside file names.
+pub fn validate_event_name(event: &str) -> Result<()> {
+    if event.trim().is_empty() {
+        anyhow::bail!("Event name is empty");
+    }
+    if let Some(character) = event.chars().find(|c| is_unsafe_character(*c)) {
+        anyhow::bail!("Event name contains a character that is not allowed in file names: {character:?}");
+    }
+    Ok(())
+}
+
+/// Returns the first pair of names that would collide on a case-insensitive file system.
+pub fn find_collision<'a>(names: impl IntoIterator<Item = &'a str>) -> Option<(&'a str, &'a str)> {
+    let mut seen: HashMap<String, &str> = HashMap::new();
+    for name in names {
+        if let Some(previous) = seen.insert(name.to_lowercase(), name) {
+            return Some((previous, name));
+        }
+    }
+    None
+}
+
+/// Replace characters that FAT file systems and hardware players can't handle.
+///
+/// Non-ASCII characters are transliterated, for example `ä` becomes `a`.
+fn sanitize_portable(text: &str) -> String {
+    deunicode::deunicode(text)
+        .chars()
+        .filter_map(|character| match character {
+            '"' => Some('\''),
+            ':' | '/' | '\\' | '|' => Some('-'),
+            '<' | '>' | '?' | '*' => None,
+            c if c.is_control() => None,
+            c => Some(c),
+        })
+        .collect()
+}
+
+fn is_unsafe_character(character: char) -> bool {
+    character.is_control() || UNSAFE_CHARACTERS.contains(&character)
+}
+
+/// Check that the rendered name can be used as a single file or directory name.
+fn validate_file_name(name: &str) -> Result<()> {
+    if name.trim().is_empty() {
+        anyhow::bail!("Name is empty");
+    }
+    if name == "." || name == ".." {
+        anyhow::bail!("Name is not allowed: '{name}'");
+    }
+    if name.contains(['/', '\\']) || name.chars().any(char::is_control) {
+        anyhow::bail!("Name contains a path separator or control character: '{name}'");
+    }
+    if name.ends_with(['.', ' ']) {
+        anyhow::bail!("Name ends with a dot or space: '{name}'");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod naming_tests {
+    use super::*;
+
+    fn values<'a>(stem: &'a str, ext: &'a str) -> NameValues<'a> {
+        let (artist, title) = utils::parse_artist_and_title(stem);
+        NameValues {
+            event: "FDO Impro",
+            number: 3,
+            number_width: 2,
+            slot: 7,
+            slot_width: 2,
+            artist: artist.unwrap_or_default(),
+            title,
+            stem,
+            ext,
+        }
+    }
+
+    #[test]
+    fn renders_default_templates() {
+        let folder = NameTemplate::folder(DEFAULT_FOLDER_TEMPLATE).expect("should parse");
+        let file = NameTemplate::file(default_file_template(DEFAULT_EVENT)).expect("should parse");
+        let values = values("Artist - Song", "mp3");
+
+        assert_eq!(folder.render(&values).expect("should render"), "FDO Impro 03");
+        assert_eq!(
+            file.render(&values).expect("should render"),
+            "07 FDO impro - Artist - Song.mp3"
+        );
+    }
+
+    #[test]
+    fn uses_event_in_default_file_name_for_custom_event() {
+        let file = NameTemplate::file(default_file_template("Disco Freestyle")).expect("should parse");
+        let values = NameValues {
+            event: "Disco Freestyle",
+            ..values("Artist - Song", "mp3")
+        };
+        assert_eq!(
+            file.render(&values).expect("should render"),
+            "07 Disco Freestyle - Artist - Song.mp3"
+        );
+    }
+
+    #[test]
+    fn renders_artist_and_title_placeholders() {
+        let file = NameTemplate::file("{slot}. {title} ({artist}).{ext}").expect("should parse");
+        let name = file.render(&values("Artist - Song", "flac")).expect("should render");
+        assert_eq!(name, "07. Song (Artist).flac");
+    }
+
+    #[test]
+    fn rejects_unknown_placeholder() {
+        let error = NameTemplate::file("{slot} {genre}.{ext}").expect_err("should fail");
+        assert!(format!("{error:#}").contains("Unknown placeholder {genre}"));
+    }
+
+    #[test]
+    fn rejects_unbalanced_braces() {
+        assert!(NameTemplate::file("{slot {stem}.{ext}").is_err());
+        assert!(NameTemplate::file("{slot}} {stem}.{ext}").is_err());
+        assert!(NameTemplate::folder("Show {number").is_err());
+    }
+
+    #[test]
+    fn requires_number_placeholder_in_folder_template() {
+        assert!(NameTemplate::folder("{event}").is_err());
+        assert!(NameTemplate::file("{stem}.{ext}").is_ok());
+    }
+
+    #[test]
+    fn rejects_track_placeholders_in_folder_template() {
+        assert!(NameTemplate::folder("{number} {artist}").is_err());
+    }
+
+    #[test]
+    fn rejects_unsafe_characters_in_template() {
+        assert!(NameTemplate::folder("{event}: {number}").is_err());
+        assert!(NameTemplate::file("{slot}/{stem}.{ext}").is_err());
+    }
+
+    #[test]
+    fn sanitizes_portable_names() {
+        let file = NameTemplate::file(DEFAULT_FILE_TEMPLATE)
+            .expect("should parse")
+            .portable(true);
+        let name = file
+            .render(&values("Kärkkäinen - What? \"Live\": Part 1|2 ...", "mp3"))
+            .expect("should render");
+        assert_eq!(name, "07 FDO impro - Karkkainen - What 'Live'- Part 1-2 ....mp3");
+    }
+
+    #[test]
+    fn shortens_portable_names_and_keeps_number_and_extension() {
+        let file = NameTemplate::file("{slot} {artist} - {title}.{ext}")
+            .expect("should parse")
+            .portable(true);
+        let long_title = "Very long title ".repeat(10);
+        let name = file
+            .render(&values(&format!("Some Artist - {long_title}"), "flac"))
+            .expect("should render");
+
+        assert!(name.chars().count() <= PORTABLE_NAME_MAX_LENGTH);
+        assert!(name.starts_with("07 Some Artist - Very long title"));
+        assert_eq!(Path::new(&name).extension(), Some("flac".as_ref()));
+    }
+
+    #[test]
+    fn avoids_reserved_portable_names() {
+        let folder = NameTemplate::folder("{event}{number}")
+            .expect("should parse")
+            .portable(true);
+        let values = NameValues {
+            event: "COM",
+            number: 1,
+            number_width: 1,
+            ..NameValues::default()
+        };
+        assert_eq!(folder.render(&values).expect("should render"), "_COM1");
+    }
+
+    #[test]
+    fn finds_case_insensitive_collisions() {
+        assert_eq!(find_collision(["01 A.mp3", "02 B.mp3"]), None);
+        assert_eq!(
+            find_collision(["01 A.mp3", "02 B.mp3", "01 a.MP3"]),
+            Some(("01 A.mp3", "01 a.MP3"))
+        );
+    }
+
+    #[test]
+    fn rejects_rendered_name_with_trailing_dot() {
+        let file = NameTemplate::file("{slot} {stem}.").expect("should parse");
+        assert!(file.render(&values("Artist - Song", "mp3")).is_err());
+    }
+}
@@ -0,0 +1,246 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+/// Playlist file extensions that can be used as the input pool.
+static PLAYLIST_EXTENSIONS: [&str; 3] = ["m3u", "m3u8", "pls"];
+
+/// Tracks listed in a playlist file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Playlist {
+    pub entries: Vec<PlaylistEntry>,
+    /// Listed paths that do not exist
+    pub missing: Vec<PathBuf>,
+    /// Listed locations that are not local files, such as stream URLs
+    pub unsupported: Vec<String>,
+}
+
+/// One track in a playlist with the artist and title the playlist gives it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlaylistEntry {
+    pub path: PathBuf,
+    pub name: Option<TrackName>,
+}
+
+/// Artist and title from an `#EXTINF` line or a PLS title.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackName {
+    pub artist: Option<String>,
+    pub title: String,
+}
+
+impl TrackName {
+    /// Parse a display name in the `Artist - Title` format.
+    fn parse(name: &str) -> Option<Self> {
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+        let (artist, title) = match name.split_once(" - ") {
+            Some((artist, title)) => (Some(artist.trim().to_string()), title.trim().to_string()),
+            None => (None, name.to_string()),
+        };
+        Some(Self { artist, title })
+    }
+}
+
+/// Returns true if the given file is an M3U, M3U8 or PLS playlist.
+pub fn is_playlist(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| {
+        let extension = extension.to_string_lossy().to_lowercase();
+        PLAYLIST_EXTENSIONS.contains(&extension.as_str())
+    })
+}
+
+/// Read the tracks listed in the playlist file.
+///
+/// Relative entries are resolved against the playlist location.
+pub fn read_playlist(path: &Path) -> Result<Playlist> {
+    let bytes = fs::read(path).context(format!("Failed to read playlist: {}", path.display()))?;
+    // Plain M3U files often use the Windows-1252 or Latin-1 encoding instead of UTF-8
+    let contents = String::from_utf8(bytes)
+        .unwrap_or_else(|error| error.into_bytes().iter().map(|&byte| char::from(byte)).collect());
+    let contents = contents.trim_start_matches('\u{feff}');
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let is_pls = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("pls"));
+    let listed = if is_pls {
+        parse_pls(contents)
+    } else {
+        parse_m3u(contents)
+    };
+
+    let mut playlist = Playlist::default();
+    for (location, name) in listed {
+        let Some(entry_path) = resolve_location(&location, base) else {
+            playlist.unsupported.push(location);
+            continue;
+        };
+        if entry_path.is_file() {
+            let path = dunce::canonicalize(&entry_path).unwrap_or(entry_path);
+            playlist.entries.push(PlaylistEntry { path, name });
+        } else {
+            playlist.missing.push(entry_path);
+        }
+    }
+    Ok(playlist)
+}
+
+/// Parse M3U and M3U8 lines, using the `#EXTINF` display name for the entry that follows it.
+fn parse_m3u(contents: &str) -> Vec<(String, Option<TrackName>)> {
+    let mut listed = Vec::new();
+    let mut name = None;
+    for line in contents.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            // Duration and optional attributes come before the first comma
+            name = info.split_once(',').and_then(|(_, display)| TrackName::parse(display));
+        } else if !line.starts_with('#') {
+            listed.push((line.to_string(), name.take()));
+        }
+    }
+    listed
+}
+
+/// Parse the numbered `FileN` and `TitleN` keys of a PLS playlist in entry order.
+fn parse_pls(contents: &str) -> Vec<(String, Option<TrackName>)> {
+    let mut files: Vec<(u32, String)> = Vec::new();
+    let mut titles: Vec<(u32, String)> = Vec::new();
+    for line in contents.lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let key = key.trim().to_lowercase();
+        let value = value.trim().to_string();
+        if let Some(number) = key.strip_prefix("file").and_then(|number| number.parse().ok()) {
+            files.push((number, value));
+        } else if let Some(number) = key.strip_prefix("title").and_then(|number| number.parse().ok()) {
+            titles.push((number, value));
+        }
+    }
+    files.sort_by_key(|(number, _)| *number);
+    files
+        .into_iter()
+        .map(|(number, location)| {
+            let name = titles
+                .iter()
+                .find(|(title_number, _)| *title_number == number)
+                .and_then(|(_, title)| TrackName::parse(title));
+            (location, name)
+        })
+        .collect()
+}
+
+/// Returns the local path for the playlist location, or `None` for URLs.
+fn resolve_location(location: &str, base: &Path) -> Option<PathBuf> {
+    let path = if let Some(path) = location.strip_prefix("file://") {
+        // Drop the host part of `file://localhost/path` and decode escaped characters
+        let path = path.strip_prefix("localhost").unwrap_or(path);
+        PathBuf::from(percent_decode(path))
+    } else if location.contains("://") {
+        return None;
+    } else {
+        // Playlists written on Windows use backslash separators
+        PathBuf::from(if cfg!(windows) {
+            location.to_string()
+        } else {
+            location.replace('\\', "/")
+        })
+    };
+    Some(if path.is_absolute() { path } else { base.join(path) })
+}
+
+/// Decode `%XX` escapes in a file URL path.
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'%'
+            && let Some(hex) = value.get(index + 1..index + 3)
+            && let Ok(byte) = u8::from_str_radix(hex, 16)
+        {
+            decoded.push(byte);
+            index += 3;
+        } else {
+            decoded.push(bytes[index]);
+            index += 1;
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+#[cfg(test)]
+mod playlist_tests {
+    use super::*;
+
+    #[test]
+    fn parses_m3u_with_extinf_names() {
+        let listed = parse_m3u(
+            "#EXTM3U\n#EXTINF:215,Artist A - First Song\nmusic/a.mp3\n\n#EXTINF:-1 tvg-id=\"x\",Untitled\nb.flac\nc.wav\n",
+        );
+        assert_eq!(
+            listed,
+            [
+                (
+                    "music/a.mp3".to_string(),
+                    Some(TrackName {
+                        artist: Some("Artist A".to_string()),
+                        title: "First Song".to_string()
+                    })
+                ),
+                (
+                    "b.flac".to_string(),
+                    Some(TrackName {
+                        artist: None,
+                        title: "Untitled".to_string()
+                    })
+                ),
+                ("c.wav".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_pls_entries_in_order() {
+        let listed = parse_pls(
+            "[playlist]\nFile2=second.mp3\nTitle2=Artist B - Two\nFile1=first.mp3\nNumberOfEntries=2\nVersion=2\n",
+        );
+        let locations: Vec<&str> = listed.iter().map(|(location, _)| location.as_str()).collect();
+        assert_eq!(locations, ["first.mp3", "second.mp3"]);
+        assert!(listed[0].1.is_none());
+        assert_eq!(
+            listed[1].1.as_ref().and_then(|name| name.artist.as_deref()),
+            Some("Artist B")
+        );
+    }
+
+    #[test]
+    fn resolves_relative_entries_and_reports_missing() {
+        let dir = tempfile::tempdir().expect("should create temp dir");
+        fs::create_dir(dir.path().join("music")).expect("should create dir");
+        let song = dir.path().join("music").join("Artist - Song.mp3");
+        fs::write(&song, b"audio").expect("should write file");
+        let path = dir.path().join("list.m3u8");
+        fs::write(
+            &path,
+            "#EXTM3U\nmusic/Artist - Song.mp3\nmusic/Missing.mp3\nhttp://example.com/stream.mp3\nfile:///nowhere/My%20Song.mp3\n",
+        )
+        .expect("should write playlist");
+
+        let playlist = read_playlist(&path).expect("should read playlist");
+        let song = dunce::canonicalize(song).expect("should canonicalize");
+        assert_eq!(playlist.entries.len(), 1);
+        assert_eq!(playlist.entries[0].path, song);
+        assert_eq!(
+            playlist.missing,
+            [
+                dir.path().join("music/Missing.mp3"),
+                PathBuf::from("/nowhere/My Song.mp3")
+            ]
+        );
+        assert_eq!(playlist.unsupported, ["http://example.com/stream.mp3"]);
+    }
+}
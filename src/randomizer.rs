@@ -1,84 +1,292 @@
 use std::cmp::min;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::time::Instant;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::{Context, Result, anyhow};
 use colored::Colorize;
+use indicatif::{ProgressBar, ProgressStyle};
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
+use rand::{RngExt, SeedableRng};
+use rayon::prelude::*;
 
+use crate::audio::{self, AudioBuffer, DecodeCheck, Silence};
+use crate::category::Categories;
+use crate::config::{Combine, Config, SafeWindow};
+use crate::cue::{self, CueSheet, CueTrack};
+use crate::duplicates::{self, Fingerprint};
+use crate::input::{self, InputFiles, InputSource, Selection};
+use crate::integrity;
+use crate::loudness::{self, Loudness};
+use crate::naming::{self, NameValues};
+use crate::playlist::TrackName;
+use crate::render::{self, TargetFormat, TrackOptions};
+use crate::rules;
+use crate::tags::{self, PlaylistTags};
 use crate::utils;
 
-/// Generate randomized play orders for the audio files from the given input directory.
+/// FAT file systems store modification times with a two second resolution.
+const FAT_TIME_RESOLUTION: Duration = Duration::from_secs(2);
+
+/// One randomized order to write to its own output directory.
+struct PermutationPlan {
+    output_name: String,
+    output_path: PathBuf,
+    files: Vec<PlannedFile>,
+}
+
+/// Input file and its numbered output file name.
+#[derive(Debug)]
+struct PlannedFile {
+    source: PathBuf,
+    name: String,
+    options: TrackOptions,
+}
+
+impl PlannedFile {
+    /// Output name with the track options for verbose output.
+    fn describe(&self) -> String {
+        self.options.describe().map_or_else(
+            || format!("  {}", self.name),
+            |options| format!("  {} ({options})", self.name),
+        )
+    }
+}
+
+/// Shared state for copying permutations concurrently.
+struct CopyContext<'a> {
+    config: &'a Config,
+    /// Output format when joining each permutation into one file
+    target_format: Option<TargetFormat>,
+    progress: ProgressBar,
+    files_done: AtomicUsize,
+    total_files: usize,
+    /// Set when any permutation fails so the other workers stop early.
+    failed: AtomicBool,
+}
+
+impl<'a> CopyContext<'a> {
+    fn new(config: &'a Config, target_format: Option<TargetFormat>, total_files: usize, total_bytes: u64) -> Self {
+        let progress = ProgressBar::new(total_bytes);
+        progress.set_style(
+            ProgressStyle::with_template(
+                "[{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}) {msg} ETA {eta}",
+            )
+            .expect("Progress bar template should be valid")
+            .progress_chars("=> "),
+        );
+        progress.set_message(format!("0/{total_files} files"));
+        Self {
+            config,
+            target_format,
+            progress,
+            files_done: AtomicUsize::new(0),
+            total_files,
+            failed: AtomicBool::new(false),
+        }
+    }
+
+    /// Print a line without messing up the progress bar.
+    fn println(&self, message: &str) {
+        self.progress.suspend(|| println!("{message}"));
+    }
+
+    /// Update progress after a file has been copied.
+    fn file_done(&self, bytes: u64) {
+        let done = self.files_done.fetch_add(1, Ordering::Relaxed) + 1;
+        self.progress.inc(bytes);
+        self.progress.set_message(format!("{done}/{} files", self.total_files));
+    }
+
+    /// Returns an error if copying should stop because of Ctrl-C or a failure in another worker.
+    fn check_stop(&self) -> Result<()> {
+        if utils::is_interrupted() {
+            anyhow::bail!("Interrupted by user");
+        }
+        if self.failed.load(Ordering::Relaxed) {
+            return Err(Cancelled.into());
+        }
+        Ok(())
+    }
+}
+
+/// Error for workers that stopped because another permutation failed.
+#[derive(Debug)]
+struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cancelled because another permutation failed")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// How each permutation picks and orders the tracks instead of shuffling all input files.
+enum Arrangement {
+    /// Order by category
+    Categories(Categories),
+    /// Random pick of some of the tracks
+    Selection(Selection),
+}
+
+impl Arrangement {
+    /// Read the categories or create the track selection if configured.
+    fn from_config(input: &InputFiles, config: &Config) -> Result<Option<Self>> {
+        if let Some(options) = &config.categories {
+            return Ok(Some(Self::Categories(Categories::load(input, options)?)));
+        }
+        if let Some(tracks) = config.tracks {
+            return Ok(Some(Self::Selection(Selection::new(input, tracks)?)));
+        }
+        Ok(None)
+    }
+
+    /// Number of tracks in each permutation.
+    fn tracks(&self) -> usize {
+        match self {
+            Self::Categories(categories) => categories.tracks_per_permutation(),
+            Self::Selection(selection) => selection.tracks(),
+        }
+    }
+
+    /// Number of different track orders, or `None` if there are too many to count.
+    fn unique_orderings(&self) -> Option<usize> {
+        match self {
+            Self::Categories(categories) => categories.unique_orderings(),
+            Self::Selection(selection) => selection.unique_orderings(),
+        }
+    }
+
+    /// Replace the tracks with a new random pick and order.
+    fn arrange(&self, files: &mut Vec<PathBuf>, rng: &mut StdRng) {
+        match self {
+            Self::Categories(categories) => categories.arrange(files, rng),
+            Self::Selection(selection) => {
+                *files = selection.pick(rng);
+                files.shuffle(rng);
+            }
+        }
+    }
+}
+
+/// Generate randomized play orders for the audio files from the given input directories and playlists.
 ///
 /// Copies audio files from input folder to new folders with numbered names in the created random order.
 /// The permutation parameter controls how many folders to generate.
-pub fn generate_unique_permutations(
-    input_path: &PathBuf,
-    output_root: PathBuf,
-    permutations: usize,
-    verbose: bool,
-    overwrite_existing: bool,
-) -> Result<()> {
+/// All orderings are generated first and the folders are then copied concurrently.
+pub fn generate_unique_permutations(inputs: &[InputSource], output_root: PathBuf, config: &Config) -> Result<()> {
     fs::create_dir_all(&output_root).context("Failed to create output root directory")?;
     let absolute_output_root =
         dunce::canonicalize(output_root).context("Failed to get absolute path for output root directory")?;
 
-    let mut files = gather_audio_files(input_path)?;
-    if files.is_empty() {
-        anyhow::bail!("No audio files found in: '{}'", input_path.display());
-    }
-
-    let num_permutations = check_permutations_count(permutations, files.len());
+    let (input, Analysis { silences, loudness, .. }) = prepare_input_files(inputs, config)?;
+    let files = &input.files;
+    let arrangement = Arrangement::from_config(&input, config)?;
+    let num_permutations = check_permutations_count(config.permutations, unique_orderings(arrangement.as_ref(), files));
+    let tracks_per_permutation = arrangement.as_ref().map_or(files.len(), Arrangement::tracks);
+    let stems = input::distinct_stems(files);
 
     println!(
-        "Generating {num_permutations} randomized audio file permutations of {} tracks to: {}\n",
-        files.len(),
+        "Generating {num_permutations} randomized audio file permutations of {tracks_per_permutation} tracks to: {}\n",
         absolute_output_root.display()
     );
 
-    let files_padding = files.len().to_string().chars().count();
-    if verbose {
-        println!("Input path: {}", input_path.display());
-        println!("Input files:");
-        for (index, file) in files.iter().enumerate() {
-            println!("{:>files_padding$}: {}", index + 1, file.display());
-        }
+    if config.verbose {
+        print_input_files(&input, arrangement.as_ref());
     }
 
+    if config.verbose || config.random_window().is_some() {
+        println!("Random seed: {}", config.seed);
+    }
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let sidecar_starts = read_excerpt_starts(files, config)?;
+    let durations = read_durations(files, config)?;
+    let gains = normalization_gains(files, config, &loudness)?;
+    report_silences(files, &silences, config);
+
     // Keep track of generated randomized orderings
     let mut orderings: HashSet<u64> = HashSet::new();
+    let mut order = files.clone();
+    let mut plans: Vec<PermutationPlan> = Vec::new();
     let permutations_padding = num_permutations.to_string().chars().count();
-    let start_time = Instant::now();
     for number in 1..=num_permutations {
-        let output_name = format!("FDO Impro {number:0permutations_padding$}");
+        let output_name = config.folder_template.render(&NameValues {
+            event: &config.event,
+            number,
+            number_width: permutations_padding,
+            ..NameValues::default()
+        })?;
+        // Draw the order before skipping existing output so the same seed always gives the same permutations
+        get_unique_file_ordering(&mut order, arrangement.as_ref(), &input.names, &mut orderings, &mut rng)?;
+        let track_options =
+            plan_track_options(&order, &sidecar_starts, &durations, &gains, &silences, config, &mut rng);
+
         let output_path = absolute_output_root.join(&output_name);
+        if output_path.exists() && !config.overwrite_existing {
+            eprintln!("Skipping already existing output dir: '{}'", output_path.display());
+            continue;
+        }
+        plans.push(PermutationPlan {
+            files: numbered_file_names(
+                &order,
+                &track_options,
+                &stems,
+                &input.names,
+                number,
+                permutations_padding,
+                config,
+            )?,
+            output_name,
+            output_path,
+        });
+    }
 
+    let target_format = if config.convert || config.combine.is_some() {
+        Some(TargetFormat::for_files(files, config)?)
+    } else {
+        None
+    };
+    if let (Some(format), true) = (target_format, config.verbose) {
         println!(
-            "{}Copying files for {}...",
-            if verbose { "\n" } else { "" },
-            output_name
+            "Output format: {} {} Hz, {} channels, {} bit\n",
+            config.output_format.extension().to_uppercase(),
+            format.sample_rate,
+            format.channels,
+            format.bit_depth
         );
-        if output_path.exists() {
-            if overwrite_existing {
-                println!(
-                    "{}",
-                    format!("Deleting existing output directory '{}'", output_path.display()).yellow()
-                );
-                fs::remove_dir_all(&output_path).context(format!(
-                    "Failed to remove existing output directory {}",
-                    output_path.display()
-                ))?;
-            } else {
-                eprintln!("Skipping already existing output dir: '{}'", output_path.display());
-                continue;
-            }
-        }
+    }
+    let largest = largest_files(files, tracks_per_permutation);
+    let permutation_size = check_free_space(&largest, plans.len(), &absolute_output_root, config, target_format)?;
 
-        get_unique_file_ordering(&mut files, &mut orderings)?;
-        fs::create_dir_all(&output_path).context("Failed to create output directory")?;
-        copy_files_with_numbered_naming(&files, &output_path, verbose)?;
+    let start_time = Instant::now();
+    let context = CopyContext::new(
+        config,
+        target_format,
+        plans.iter().map(|plan| plan.files.len()).sum(),
+        permutation_size * plans.len() as u64,
+    );
+    // Before copying nothing needs cleaning up, so Ctrl-C during the input analysis exits right away
+    utils::install_interrupt_handler()?;
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(config.jobs)
+        .build()
+        .context("Failed to create worker thread pool")?;
+
+    let results: Vec<Result<()>> =
+        pool.install(|| plans.par_iter().map(|plan| write_permutation(plan, &context)).collect());
+    let mut errors: Vec<anyhow::Error> = results.into_iter().filter_map(Result::err).collect();
+    if errors.is_empty() {
+        context.progress.finish_and_clear();
+    } else {
+        context.progress.abandon();
+        // Report the original failure instead of the workers that were stopped because of it
+        let index = errors.iter().position(|error| !error.is::<Cancelled>()).unwrap_or(0);
+        return Err(errors.swap_remove(index));
     }
 
     let elapsed = start_time.elapsed();
@@ -87,77 +295,805 @@ pub fn generate_unique_permutations(
     Ok(())
 }
 
+/// Print the input paths, the numbered input files and their categories.
+fn print_input_files(input: &InputFiles, arrangement: Option<&Arrangement>) {
+    let files = &input.files;
+    let files_padding = files.len().to_string().chars().count();
+    for source in &input.inputs {
+        println!("Input path: {source}");
+    }
+    println!("Input files:");
+    for (index, file) in files.iter().enumerate() {
+        println!("{:>files_padding$}: {}", index + 1, file.display());
+    }
+    println!();
+    if let Some(Arrangement::Categories(categories)) = arrangement {
+        categories.print();
+    }
+}
+
+/// Number of different track orders, or `None` if there are too many to count.
+///
+/// Without an arrangement every permutation holds all the files.
+fn unique_orderings(arrangement: Option<&Arrangement>, files: &[PathBuf]) -> Option<usize> {
+    arrangement.map_or_else(
+        || utils::k_permutations(files.len(), files.len()),
+        Arrangement::unique_orderings,
+    )
+}
+
 /// Return number of permutations that is doable.
 ///
-/// Might run into problems with uniqueness if there is only a small number of files.
-fn check_permutations_count(permutations: usize, num_files: usize) -> usize {
-    if num_files <= 20 {
-        // Calculate factorial for number of files
-        let max_unique_permutations: usize = (1..=num_files).product();
-        if permutations > max_unique_permutations {
-            println!(
+/// Limits the count to the number of unique orderings of the tracks in each permutation,
+/// which is `None` when there are too many to count.
+fn check_permutations_count(permutations: usize, max_unique_permutations: Option<usize>) -> usize {
+    let Some(max_unique_permutations) = max_unique_permutations else {
+        return permutations;
+    };
+    if permutations > max_unique_permutations {
+        println!(
+            "{}",
+            format!("Limiting permutations to {max_unique_permutations} possible unique orderings!").red()
+        );
+    }
+    min(permutations, max_unique_permutations)
+}
+
+/// Check that the output root filesystem has enough free space for the given number of permutations.
+///
+/// Each permutation is a full copy of all input files,
+/// or the estimated size of the rendered files or combined file when rendering audio.
+/// Links and playlists need no space for the audio files, so they are not checked.
+/// Returns the size of a single permutation in bytes.
+fn check_free_space(
+    files: &[PathBuf],
+    permutations: usize,
+    output_root: &Path,
+    config: &Config,
+    target_format: Option<TargetFormat>,
+) -> Result<u64> {
+    let permutation_size = match (config.combine, target_format) {
+        (Some(_), Some(format)) => render::estimated_combined_size(files, format, config)?,
+        _ => files_size(files, config, target_format)?,
+    };
+    if permutations == 0 || !config.writes_audio() {
+        return Ok(permutation_size);
+    }
+    let required_space = permutation_size * permutations as u64;
+    let available_space = fs4::available_space(output_root).context(format!(
+        "Failed to get available space for output root: {}",
+        output_root.display()
+    ))?;
+
+    if let Some(message) = free_space_shortfall(permutation_size, permutations, available_space) {
+        anyhow::bail!(
+            "Not enough free space in '{}': {} permutations need {}, only {} available.\n{message}",
+            output_root.display(),
+            permutations,
+            utils::format_size(required_space),
+            utils::format_size(available_space),
+        );
+    }
+    Ok(permutation_size)
+}
+
+/// The given number of largest input files, to estimate the space one permutation needs
+/// when it only uses some of the input files.
+fn largest_files(files: &[PathBuf], count: usize) -> Vec<PathBuf> {
+    if count >= files.len() {
+        return files.to_vec();
+    }
+    let mut by_size: Vec<(u64, &PathBuf)> = files
+        .iter()
+        .map(|file| (fs::metadata(file).map_or(0, |metadata| metadata.len()), file))
+        .collect();
+    by_size.sort_by_key(|&(size, _)| std::cmp::Reverse(size));
+    by_size.into_iter().take(count).map(|(_, file)| file.clone()).collect()
+}
+
+/// Total size in bytes of the copied or rendered output files.
+fn files_size(files: &[PathBuf], config: &Config, target_format: Option<TargetFormat>) -> Result<u64> {
+    let mut size: u64 = 0;
+    for file in files {
+        size += if config.renders_audio() {
+            render::estimated_size(file, target_format, config)?
+        } else {
+            fs::metadata(file)
+                .context(format!("Failed to read file metadata: {}", file.display()))?
+                .len()
+        };
+    }
+    Ok(size)
+}
+
+/// Returns a suggestion message if the permutations do not fit into the available space.
+fn free_space_shortfall(permutation_size: u64, permutations: usize, available_space: u64) -> Option<String> {
+    if permutation_size.saturating_mul(permutations as u64) <= available_space {
+        return None;
+    }
+    let fits = available_space.checked_div(permutation_size).unwrap_or(0);
+    let suggestion = "or use --link or --playlist-only to write the permutations without copying the audio files";
+    if fits == 0 {
+        Some(format!(
+            "Not even a single permutation fits, choose another output path with the --output option, {suggestion}"
+        ))
+    } else {
+        Some(format!(
+            "At most {fits} permutations fit, reduce the number of permutations, choose another output path, {suggestion}"
+        ))
+    }
+}
+
+/// Temporary output directory that is removed on drop unless it has been moved into place.
+///
+/// Created as a hidden sibling of the final output directory so the rename stays on the same filesystem.
+struct StagingDir {
+    path: PathBuf,
+    committed: bool,
+}
+
+impl StagingDir {
+    /// Create an empty staging directory for the given final output path.
+    ///
+    /// Removes any stale staging directory left behind by a previous run that was killed.
+    fn create(output_path: &Path) -> Result<Self> {
+        let path = sibling_path(output_path, "partial")?;
+        if path.exists() {
+            fs::remove_dir_all(&path)
+                .context(format!("Failed to remove stale staging directory {}", path.display()))?;
+        }
+        fs::create_dir_all(&path).context("Failed to create output directory")?;
+        Ok(Self { path, committed: false })
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Move the staging directory to the final output path.
+    ///
+    /// An existing output directory is only deleted after the new one is in place.
+    /// Returns true if an existing output directory was replaced.
+    fn commit(mut self, output_path: &Path) -> Result<bool> {
+        let backup_path = if output_path.exists() {
+            let backup_path = sibling_path(output_path, "old")?;
+            if backup_path.exists() {
+                fs::remove_dir_all(&backup_path)?;
+            }
+            fs::rename(output_path, &backup_path).context(format!(
+                "Failed to move existing output directory {}",
+                output_path.display()
+            ))?;
+            Some(backup_path)
+        } else {
+            None
+        };
+
+        if let Err(error) = fs::rename(&self.path, output_path) {
+            if let Some(backup_path) = &backup_path {
+                let _ = fs::rename(backup_path, output_path);
+            }
+            return Err(error).context(format!("Failed to create output directory {}", output_path.display()));
+        }
+        self.committed = true;
+
+        if let Some(backup_path) = &backup_path {
+            fs::remove_dir_all(backup_path).context(format!(
+                "Failed to remove existing output directory {}",
+                backup_path.display()
+            ))?;
+        }
+        Ok(backup_path.is_some())
+    }
+}
+
+impl Drop for StagingDir {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+}
+
+/// Hidden sibling path for the given output path, for example `.FDO Impro 01.partial`.
+fn sibling_path(output_path: &Path, suffix: &str) -> Result<PathBuf> {
+    #[allow(clippy::unnecessary_debug_formatting)]
+    let name = output_path
+        .file_name()
+        .and_then(|os_str| os_str.to_str())
+        .ok_or_else(|| anyhow!("Invalid output directory name: {output_path:?}"))?;
+    Ok(output_path.with_file_name(format!(".{name}.{suffix}")))
+}
+
+/// Copy the files for one permutation into its output directory.
+///
+/// Files are copied into a temporary sibling directory first,
+/// so a partially copied permutation never looks like a complete one.
+fn write_permutation(plan: &PermutationPlan, context: &CopyContext) -> Result<()> {
+    if context.config.verbose {
+        context.println(&format!("Copying files for {}...", plan.output_name));
+    }
+    let staging_dir = StagingDir::create(&plan.output_path)?;
+    let result = match (context.config.combine, context.target_format) {
+        (Some(combine), Some(format)) => write_combined_file(plan, combine, format, staging_dir.path(), context),
+        _ if context.config.playlist_only => write_playlist_file(plan, staging_dir.path(), context),
+        _ => copy_files_with_numbered_naming(plan, staging_dir.path(), context),
+    }
+    .and_then(|()| staging_dir.commit(&plan.output_path));
+    match result {
+        Ok(replaced) => {
+            if replaced {
+                context.println(
+                    &format!("Replaced existing output directory '{}'", plan.output_path.display())
+                        .yellow()
+                        .to_string(),
+                );
+            }
+            if context.config.player_order && context.config.verbose {
+                verify_playback_order(plan, context)?;
+            }
+            Ok(())
+        }
+        Err(error) => {
+            context.failed.store(true, Ordering::Relaxed);
+            Err(error)
+        }
+    }
+}
+
+/// Read excerpt start positions from sidecar CUE files when cutting excerpts at fixed positions.
+fn read_excerpt_starts(files: &[PathBuf], config: &Config) -> Result<HashMap<PathBuf, f64>> {
+    let mut starts = HashMap::new();
+    if config.excerpt.is_none() || config.random_window().is_some() {
+        return Ok(starts);
+    }
+    for file in files {
+        if let Some(start) = cue::read_sidecar_start(file)? {
+            if config.verbose {
+                println!("Excerpt start {start:.2}s from {}", cue::sidecar_path(file).display());
+            }
+            starts.insert(file.clone(), start);
+        }
+    }
+    Ok(starts)
+}
+
+/// Read track durations in seconds when drawing random excerpt starts.
+fn read_durations(files: &[PathBuf], config: &Config) -> Result<HashMap<PathBuf, f64>> {
+    let mut durations = HashMap::new();
+    if config.random_window().is_none() {
+        return Ok(durations);
+    }
+    for file in files {
+        let duration = tags::read_properties(file)?.duration().as_secs_f64();
+        durations.insert(file.clone(), duration);
+    }
+    Ok(durations)
+}
+
+/// Excerpt start and gain for each file in the given order.
+///
+/// Random starts are drawn from the run RNG in playback order,
+/// so the same seed gives the same starts.
+fn plan_track_options(
+    files: &[PathBuf],
+    sidecar_starts: &HashMap<PathBuf, f64>,
+    durations: &HashMap<PathBuf, f64>,
+    gains: &HashMap<PathBuf, f64>,
+    silences: &HashMap<PathBuf, Silence>,
+    config: &Config,
+    rng: &mut StdRng,
+) -> Vec<TrackOptions> {
+    files
+        .iter()
+        .map(|file| {
+            let trim = silences.get(file).copied().filter(|_| config.trims_silence());
+            let excerpt_start = match (config.excerpt, config.random_window(), durations.get(file)) {
+                (Some(excerpt), Some(window), Some(&duration)) => {
+                    // Excerpt starts count from the end of the trimmed leading silence
+                    let duration = trim.map_or(duration, |trim| (duration - trim.leading - trim.trailing).max(0.0));
+                    Some(random_excerpt_start(duration, excerpt.length, window, rng))
+                }
+                _ => sidecar_starts.get(file).copied(),
+            };
+            TrackOptions {
+                excerpt_start,
+                gain_db: gains.get(file).copied(),
+                trim,
+            }
+        })
+        .collect()
+}
+
+/// Report the leading and trailing silence of the input files.
+///
+/// Verbose output lists the silence of every track,
+/// otherwise only tracks with silence are reported when not trimming.
+fn report_silences(files: &[PathBuf], silences: &HashMap<PathBuf, Silence>, config: &Config) {
+    let Some(detection) = config.silence else {
+        return;
+    };
+    if config.verbose {
+        println!("Silence (start / end):");
+    }
+    for file in files {
+        let silence = silences[file];
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        if config.verbose {
+            println!("{:>6.2}s {:>6.2}s  {name}", silence.leading, silence.trailing);
+        } else if !detection.trim && (silence.leading > 0.0 || silence.trailing > 0.0) {
+            eprintln!(
                 "{}",
-                format!("Limiting permutations to {max_unique_permutations} possible unique orderings!").red()
+                format!(
+                    "Silence of {:.2}s at start and {:.2}s at end: {name}",
+                    silence.leading, silence.trailing
+                )
+                .yellow()
             );
         }
-        min(permutations, max_unique_permutations)
-    } else {
-        permutations
     }
+    if config.verbose {
+        println!();
+    }
+}
+
+/// Measure the loudness of the input files and print it if requested.
+///
+/// Files measured in the decode pass of the input analysis are not decoded again.
+/// Returns the gain in dB for each file when normalizing.
+fn normalization_gains(
+    files: &[PathBuf],
+    config: &Config,
+    measured: &HashMap<PathBuf, Loudness>,
+) -> Result<HashMap<PathBuf, f64>> {
+    if !config.measure_loudness() {
+        return Ok(HashMap::new());
+    }
+    let cache_path = loudness::default_cache_path();
+    let measurements = loudness::measure_files(files, cache_path.as_deref(), measured)?;
+    if config.loudness_report || config.verbose {
+        println!("Loudness:");
+        for file in files {
+            let measurement = measurements[file];
+            println!(
+                "{:>6.1} LUFS {:>6.1} dBTP  {}",
+                measurement.integrated,
+                measurement.true_peak,
+                file.file_name().unwrap_or_default().to_string_lossy()
+            );
+        }
+        println!();
+    }
+    let Some(target) = config.normalize else {
+        return Ok(HashMap::new());
+    };
+    Ok(measurements
+        .into_iter()
+        .map(|(file, measurement)| (file, measurement.normalization_gain(target)))
+        .collect())
+}
+
+/// Draw a random excerpt start in seconds inside the safe window of the track.
+///
+/// Starts are rounded to milliseconds so the recorded value reproduces the excerpt exactly.
+/// If the track is too short for the excerpt between the skipped intro and outro,
+/// the excerpt starts right after the intro or as late as possible.
+fn random_excerpt_start(duration: f64, length: f64, window: SafeWindow, rng: &mut StdRng) -> f64 {
+    let earliest = window.skip_intro;
+    let latest = duration - window.skip_outro - length;
+    if latest <= earliest {
+        return earliest.min((duration - length).max(0.0));
+    }
+    (rng.random_range(earliest..=latest) * 1000.0).round() / 1000.0
+}
+
+/// Render the numbered output file names for the given file order.
+/// Files with a distinct stem use it instead of their own file stem,
+/// and the artist and title from a playlist replace the ones parsed from the file name.
+///
+/// Rendered files get the extension of the render format.
+fn numbered_file_names(
+    files: &[PathBuf],
+    track_options: &[TrackOptions],
+    stems: &HashMap<PathBuf, String>,
+    names: &HashMap<PathBuf, TrackName>,
+    number: usize,
+    number_width: usize,
+    config: &Config,
+) -> Result<Vec<PlannedFile>> {
+    let slot_width = files.len().to_string().len();
+    let planned = files
+        .iter()
+        .zip(track_options)
+        .enumerate()
+        .map(|(index, (source, &options))| {
+            let mut values = NameValues {
+                number,
+                number_width,
+                slot: index + 1,
+                slot_width,
+                ..NameValues::for_file(source, &config.event)?
+            };
+            if let Some(stem) = stems.get(source) {
+                values.stem = stem;
+            }
+            if let Some(name) = names.get(source) {
+                values.title = &name.title;
+                if let Some(artist) = &name.artist {
+                    values.artist = artist;
+                }
+            }
+            if config.renders_audio() {
+                values.ext = config.output_format.extension();
+            }
+            let name = config.file_template.render(&values)?;
+            Ok(PlannedFile {
+                source: source.clone(),
+                name,
+                options,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // Templates without {slot}, sanitising and shortening can make different input names end up the same
+    if let Some((first, second)) = naming::find_collision(planned.iter().map(|file| file.name.as_str())) {
+        anyhow::bail!("Output file names collide: '{first}' and '{second}'");
+    }
+    Ok(planned)
 }
 
 /// Copy files to given new folder with a running index added to the start of the filename.
-fn copy_files_with_numbered_naming(files: &[PathBuf], output_path: &Path, verbose: bool) -> Result<()> {
-    for (index, original_file) in files.iter().enumerate() {
-        #[allow(clippy::unnecessary_debug_formatting)]
-        let file_name = original_file
-            .file_name()
-            .and_then(|os_str| os_str.to_str())
-            .ok_or_else(|| anyhow!("Invalid file name in path: {original_file:?}"))?;
+///
+/// Files are always written one at a time in playback order.
+/// With player order enabled, the modification times are also set in playback order.
+/// Stops with an error if the user presses Ctrl-C or another permutation failed.
+fn copy_files_with_numbered_naming(plan: &PermutationPlan, output_path: &Path, context: &CopyContext) -> Result<()> {
+    let files = &plan.files;
+    // Start in the past so the last file doesn't get a modification time in the future
+    #[allow(clippy::cast_possible_truncation)]
+    let modified_base = SystemTime::now() - FAT_TIME_RESOLUTION * files.len() as u32;
+    for (index, file) in files.iter().enumerate() {
+        context.check_stop()?;
 
-        let width = files.len().to_string().len();
-        let number = index + 1;
-        let new_file_name = format!("{number:0width$} FDO impro - {file_name}");
-        let new_file = output_path.join(new_file_name);
+        if context.config.verbose {
+            context.println(&file.describe());
+        }
 
-        if verbose {
-            println!("  Copying to: {}", new_file.display());
+        let new_file = output_path.join(&file.name);
+        let bytes = if context.config.renders_audio() {
+            render::render_file(
+                &file.source,
+                &new_file,
+                file.options,
+                context.target_format,
+                context.config,
+            )?
+        } else if context.config.link {
+            fs::hard_link(&file.source, &new_file).context(format!(
+                "Failed to link file, the output must be on the same filesystem as the input: {}",
+                file.source.display()
+            ))?;
+            fs::metadata(&new_file)
+                .context(format!("Failed to read file metadata: {}", new_file.display()))?
+                .len()
+        } else {
+            let bytes =
+                fs::copy(&file.source, &new_file).context(format!("Failed to copy file: {}", file.source.display()))?;
+            utils::ensure_writable(&new_file)?;
+            bytes
+        };
+
+        if let (Some(start), Some(_)) = (file.options.excerpt_start, context.config.random_window()) {
+            let comment = format!("Excerpt start {start:.3}s, seed {}", context.config.seed);
+            tags::write_comment(&new_file, &comment)?;
+        }
+
+        if context.config.write_tags {
+            let tags = PlaylistTags {
+                track: index + 1,
+                track_total: files.len(),
+                album: &plan.output_name,
+                album_artist: &context.config.event,
+            };
+            tags::write_playlist_tags(&new_file, &tags)?;
         }
 
-        fs::copy(original_file, &new_file).context("Failed to copy file")?;
+        if context.config.player_order {
+            #[allow(clippy::cast_possible_truncation)]
+            let modified = modified_base + FAT_TIME_RESOLUTION * index as u32;
+            set_modified_time(&new_file, modified, context.config.fsync)?;
+        }
+        context.file_done(bytes);
+    }
+
+    if context.config.fsync {
+        sync_directory(output_path)?;
     }
     Ok(())
 }
 
-/// Get a list of all the audio files present in the input path.
-/// Note: non-recursive so only looks at the files directly in the given directory.
-fn gather_audio_files(input_path: &PathBuf) -> Result<Vec<PathBuf>> {
-    let mut files: Vec<PathBuf> = fs::read_dir(input_path)
-        .context("Failed to read input directory")?
-        .filter_map(std::result::Result::ok)
-        .filter_map(|entry| {
-            let path = entry.path();
-            if utils::is_audio_file(&path) { Some(path) } else { None }
-        })
-        .collect();
+/// Write an M3U8 playlist named after the permutation that lists the input files in playback order.
+fn write_playlist_file(plan: &PermutationPlan, output_path: &Path, context: &CopyContext) -> Result<()> {
+    let mut lines = vec!["#EXTM3U".to_string()];
+    for file in &plan.files {
+        context.check_stop()?;
+        if context.config.verbose {
+            context.println(&file.describe());
+        }
+        let name = file.source.file_stem().unwrap_or_default().to_string_lossy();
+        lines.push(format!("#EXTINF:-1,{name}"));
+        lines.push(file.source.display().to_string());
+        context.file_done(fs::metadata(&file.source).map_or(0, |metadata| metadata.len()));
+    }
+    lines.push(String::new());
+    let playlist_file = output_path.join(format!("{}.m3u8", plan.output_name));
+    fs::write(&playlist_file, lines.join("\n"))
+        .context(format!("Failed to write playlist: {}", playlist_file.display()))
+}
+
+/// Decode the planned files in order and join them into one audio file named after the permutation.
+///
+/// Tracks are converted to the combined format and separated by the configured gap or crossfade.
+/// The whole permutation is kept in memory, so combined files are written one at a time.
+/// A CUE sheet with the track start positions is written next to the audio file.
+fn write_combined_file(
+    plan: &PermutationPlan,
+    combine: Combine,
+    format: TargetFormat,
+    output_path: &Path,
+    context: &CopyContext,
+) -> Result<()> {
+    let config = context.config;
+    let mut combined = AudioBuffer::empty(format.sample_rate, format.channels);
+    let mut cue_tracks = Vec::with_capacity(plan.files.len());
+    for (index, file) in plan.files.iter().enumerate() {
+        context.check_stop()?;
+
+        if config.verbose {
+            context.println(&file.describe());
+        }
+
+        let mut track = render::render_track(&file.source, file.options, config)?
+            .convert(format.sample_rate, format.channels)
+            .context(format!("Failed to convert audio: {}", file.source.display()))?;
+        if combine.match_gain {
+            let gain = render::match_gain(&mut track);
+            if config.verbose {
+                context.println(&format!("    gain {gain:+.1} dB"));
+            }
+        }
+
+        let start = if let Some(crossfade) = combine.crossfade {
+            combined.append_crossfaded(&track, crossfade)?
+        } else {
+            if index > 0 {
+                combined.append_silence(combine.gap);
+            }
+            let start = combined.duration();
+            combined.append(&track)?;
+            start
+        };
+        cue_tracks.push(CueTrack::for_file(&file.source, start)?);
+        context.file_done(format.size_of(track.duration()));
+    }
+
+    let file_name = format!("{}.{}", plan.output_name, config.output_format.extension());
+    let new_file = output_path.join(&file_name);
+    audio::write_audio_file(&new_file, &combined, config.output_format, format.bit_depth)?;
+    let cue_sheet = CueSheet {
+        performer: config.event.clone(),
+        title: plan.output_name.clone(),
+        file_name,
+        format: config.output_format,
+        tracks: cue_tracks,
+    };
+    cue_sheet.write(&new_file)?;
+
+    if config.random_window().is_some() {
+        let starts: Vec<String> = plan
+            .files
+            .iter()
+            .filter_map(|file| file.options.excerpt_start)
+            .map(|start| format!("{start:.3}s"))
+            .collect();
+        let comment = format!("Excerpt starts {}, seed {}", starts.join(" "), config.seed);
+        tags::write_comment(&new_file, &comment)?;
+    }
+    Ok(())
+}
+
+/// Set the file modification time and optionally flush the file to disk.
+fn set_modified_time(path: &Path, modified: SystemTime, sync: bool) -> Result<()> {
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .open(path)
+        .context(format!("Failed to open file: {}", path.display()))?;
+    file.set_modified(modified)
+        .context(format!("Failed to set modification time: {}", path.display()))?;
+    if sync {
+        file.sync_all()
+            .context(format!("Failed to sync file to disk: {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Flush directory entries to disk.
+#[cfg(unix)]
+fn sync_directory(path: &Path) -> Result<()> {
+    fs::File::open(path)
+        .and_then(|dir| dir.sync_all())
+        .context(format!("Failed to sync directory to disk: {}", path.display()))
+}
+
+/// Directories can't be opened as files on Windows, the file syncs are enough there.
+#[cfg(not(unix))]
+#[allow(clippy::unnecessary_wraps)]
+fn sync_directory(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Print the written files with their modification times and whether the times follow the playback order.
+///
+/// Only the modification times are checked. Players that use the directory entry order
+/// get the write order on FAT and exFAT sticks, but other file systems do not keep entries in write order.
+fn verify_playback_order(plan: &PermutationPlan, context: &CopyContext) -> Result<()> {
+    let mut times: Vec<(&str, SystemTime)> = Vec::new();
+    for file in &plan.files {
+        let path = plan.output_path.join(&file.name);
+        let modified = fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .context(format!("Failed to read modification time: {}", path.display()))?;
+        times.push((&file.name, modified));
+    }
+    let Some(&(_, first)) = times.first() else {
+        return Ok(());
+    };
+
+    let mut lines = vec!["  Written order with modification times:".to_string()];
+    for (name, modified) in &times {
+        let offset = modified.duration_since(first).unwrap_or_default().as_secs();
+        lines.push(format!("    {:>6}  {name}", format!("+{offset}s")));
+    }
+    context.println(&lines.join("\n"));
+    if times.windows(2).all(|pair| pair[0].1 < pair[1].1) {
+        context.println(&"  Modification times follow the playback order".green().to_string());
+    } else {
+        context.println(
+            &"  Modification times do not follow the playback order"
+                .yellow()
+                .to_string(),
+        );
+    }
+    Ok(())
+}
+
+/// Gather the input files and check them before generating.
+///
+/// Broken files and duplicates are reported and optionally left out,
+/// then the remaining files are validated against the competition rules.
+fn prepare_input_files(inputs: &[InputSource], config: &Config) -> Result<(InputFiles, Analysis)> {
+    let mut input = input::gather_inputs(inputs, config.recursive, &config.filter)?;
+    if config.verbose {
+        input.print_skipped();
+    }
+    let (mut files, mut analysis) = analyse_input_files(std::mem::take(&mut input.files), config)?;
+    if config.find_duplicates {
+        let fingerprints = std::mem::take(&mut analysis.fingerprints);
+        files = duplicates::check_duplicates(files, &fingerprints, config.keep_best)?;
+    }
+    if files.is_empty() {
+        let paths: Vec<String> = inputs
+            .iter()
+            .map(|input| format!("'{}'", input.path.display()))
+            .collect();
+        if !config.filter.is_empty() {
+            anyhow::bail!("No audio files left after filtering: {}", paths.join(", "));
+        }
+        anyhow::bail!("No audio files found in: {}", paths.join(", "));
+    }
+    if let Some(rules) = &config.rules {
+        rules::enforce(&files, rules, config.verbose)?;
+    }
+    input.files = files;
+    Ok((input, analysis))
+}
+
+/// Silence, loudness and fingerprints of the input files from one decode pass.
+#[derive(Debug, Default)]
+struct Analysis {
+    silences: HashMap<PathBuf, Silence>,
+    loudness: HashMap<PathBuf, Loudness>,
+    fingerprints: HashMap<PathBuf, Fingerprint>,
+}
 
-    // Sort the files and remove duplicates
-    files.sort();
-    files.dedup();
+/// Decode result, silence, loudness and fingerprint of one input file.
+struct TrackAnalysis {
+    check: DecodeCheck,
+    silence: Option<Silence>,
+    loudness: Option<Loudness>,
+    fingerprint: Option<Fingerprint>,
+}
+
+/// Check the input files for broken files and analyse them, decoding each file at most once.
+///
+/// Files are decoded here when verifying them completely, detecting silence or finding duplicates,
+/// and the loudness and fingerprint are computed from the same decode when needed.
+/// Otherwise only the file headers are checked and loudness is left to the loudness cache.
+fn analyse_input_files(files: Vec<PathBuf>, config: &Config) -> Result<(Vec<PathBuf>, Analysis)> {
+    let mut analysis = Analysis::default();
+    if !config.verify && config.silence.is_none() && !config.find_duplicates {
+        let files = integrity::check_input_files(files, false, config.skip_broken)?;
+        return Ok((files, analysis));
+    }
+    let files = if config.verify {
+        files
+    } else {
+        integrity::check_input_files(files, false, config.skip_broken)?
+    };
+
+    let results: Vec<Result<TrackAnalysis>> = files.par_iter().map(|file| analyse_file(file, config)).collect();
+    let mut broken = Vec::new();
+    for (file, result) in files.iter().zip(results) {
+        if config.verify
+            && let Some(reason) = integrity::check_decoded_file(file, result.as_ref().map(|track| &track.check))
+        {
+            broken.push(integrity::BrokenFile {
+                path: file.clone(),
+                reason,
+            });
+            continue;
+        }
+        let track = result?;
+        if let Some(silence) = track.silence {
+            analysis.silences.insert(file.clone(), silence);
+        }
+        if let Some(loudness) = track.loudness {
+            analysis.loudness.insert(file.clone(), loudness);
+        }
+        if let Some(fingerprint) = track.fingerprint {
+            analysis.fingerprints.insert(file.clone(), fingerprint);
+        }
+    }
+    let files = integrity::exclude_broken_files(files, &broken, config.skip_broken)?;
+    Ok((files, analysis))
+}
 
-    Ok(files)
+/// Decode the file and measure the silence, loudness and fingerprint as configured.
+fn analyse_file(file: &Path, config: &Config) -> Result<TrackAnalysis> {
+    let (audio, check) = audio::decode_file_checked(file)?;
+    let silence = config
+        .silence
+        .map(|detection| audio.detect_silence(detection.threshold_db, detection.min_duration));
+    let loudness = if config.measure_loudness() {
+        Some(loudness::measure(&audio).context(format!("Failed to measure loudness: {}", file.display()))?)
+    } else {
+        None
+    };
+    let fingerprint = config.find_duplicates.then(|| Fingerprint::from_audio(&audio));
+    Ok(TrackAnalysis {
+        check,
+        silence,
+        loudness,
+        fingerprint,
+    })
 }
 
 /// Returns true if there are consecutive files with the same artist name.
 ///
-/// This assumes all files are named in the format: <artist> - <title>.
-fn check_consecutive_tracks_from_same_artist(tracks: &[PathBuf]) -> bool {
+/// Uses the artist from the playlist entry if there is one,
+/// otherwise this assumes all files are named in the format: <artist> - <title>.
+fn check_consecutive_tracks_from_same_artist(tracks: &[PathBuf], names: &HashMap<PathBuf, TrackName>) -> bool {
     if tracks.len() < 2 {
         return false;
     }
     tracks
         .iter()
-        .filter_map(|path| path.file_stem()?.to_str())
+        .filter_map(|path| {
+            if let Some(artist) = names.get(path).and_then(|name| name.artist.as_deref()) {
+                return Some(artist);
+            }
+            path.file_stem()?.to_str()
+        })
         .map(|s| s.split(" - ").next().unwrap_or(s))
         .collect::<Vec<_>>()
         .windows(2)
@@ -169,13 +1105,23 @@ fn check_consecutive_tracks_from_same_artist(tracks: &[PathBuf]) -> bool {
 
 /// Keep shuffling song order until there are no consecutive tracks from the same artists,
 /// and the order is different from all previous orderings.
-fn get_unique_file_ordering(files: &mut Vec<PathBuf>, orderings: &mut HashSet<u64>) -> Result<()> {
-    let mut rng = rand::rng();
-    files.shuffle(&mut rng);
+/// With an arrangement, each new order picks and orders the tracks with it.
+fn get_unique_file_ordering(
+    files: &mut Vec<PathBuf>,
+    arrangement: Option<&Arrangement>,
+    names: &HashMap<PathBuf, TrackName>,
+    orderings: &mut HashSet<u64>,
+    rng: &mut StdRng,
+) -> Result<()> {
+    let mut shuffle = |files: &mut Vec<PathBuf>| match arrangement {
+        Some(arrangement) => arrangement.arrange(files, rng),
+        None => files.shuffle(rng),
+    };
+    shuffle(files);
     let mut hash = utils::get_ordering_hash(files);
     let mut tries: usize = 0;
-    while check_consecutive_tracks_from_same_artist(files) || orderings.contains(&hash) {
-        files.shuffle(&mut rng);
+    while check_consecutive_tracks_from_same_artist(files, names) || orderings.contains(&hash) {
+        shuffle(files);
         hash = utils::get_ordering_hash(files);
         tries += 1;
         if tries > 1000 {
@@ -195,28 +1141,120 @@ mod permutation_tests {
         names.iter().map(PathBuf::from).collect()
     }
 
+    fn test_rng() -> StdRng {
+        StdRng::seed_from_u64(7)
+    }
+
+    fn test_config(arguments: &[&str]) -> Config {
+        use clap::Parser;
+        let args =
+            crate::Args::try_parse_from([&["fdo-randomizer", "music"], arguments].concat()).expect("should parse");
+        Config::from_args(&args, 1).expect("should create config")
+    }
+
+    #[test]
+    fn reports_colliding_file_names_without_slot() {
+        let tracks = dummy_tracks(&["Artist A - One.mp3", "Artist B - One.mp3", "Artist A - Two.mp3"]);
+        let options = vec![TrackOptions::default(); tracks.len()];
+        let render = |template: &str| {
+            numbered_file_names(
+                &tracks,
+                &options,
+                &HashMap::new(),
+                &HashMap::new(),
+                1,
+                1,
+                &test_config(&["--file-name", template]),
+            )
+        };
+
+        assert!(render("{title} - {artist}.{ext}").is_ok());
+        let error = render("{artist}.{ext}").expect_err("should collide");
+        assert!(
+            error
+                .to_string()
+                .contains("Output file names collide: 'Artist A.mp3' and 'Artist A.mp3'")
+        );
+    }
+
+    #[test]
+    fn draws_random_excerpt_start_inside_trimmed_track() {
+        let config = test_config(&[
+            "--excerpt",
+            "10",
+            "--random-start",
+            "--skip-intro",
+            "0",
+            "--skip-outro",
+            "0",
+            "--trim-silence",
+        ]);
+        let tracks = dummy_tracks(&["Artist A - One.wav"]);
+        let durations = HashMap::from([(tracks[0].clone(), 30.0)]);
+        let silence = Silence {
+            leading: 5.0,
+            trailing: 15.0,
+        };
+        let silences = HashMap::from([(tracks[0].clone(), silence)]);
+
+        let options = plan_track_options(
+            &tracks,
+            &HashMap::new(),
+            &durations,
+            &HashMap::new(),
+            &silences,
+            &config,
+            &mut test_rng(),
+        );
+
+        // Only 10 seconds of audio remain after trimming, so the excerpt covers all of it
+        assert_eq!(options[0].excerpt_start, Some(0.0));
+        assert_eq!(options[0].trim, Some(silence));
+    }
+
     #[test]
     fn limits_permutations_to_factorial_for_small_track_counts() {
-        assert_eq!(check_permutations_count(10, 3), 6);
-        assert_eq!(check_permutations_count(6, 3), 6);
-        assert_eq!(check_permutations_count(1, 0), 1);
+        assert_eq!(check_permutations_count(10, utils::k_permutations(3, 3)), 6);
+        assert_eq!(check_permutations_count(6, utils::k_permutations(3, 3)), 6);
+        assert_eq!(check_permutations_count(1, utils::k_permutations(0, 0)), 1);
     }
 
     #[test]
     fn does_not_limit_permutations_for_large_track_counts() {
-        assert_eq!(check_permutations_count(1_000, 21), 1_000);
+        assert_eq!(check_permutations_count(1_000, utils::k_permutations(21, 21)), 1_000);
+    }
+
+    #[test]
+    fn limits_permutations_to_orderings_of_picked_tracks() {
+        let input = InputFiles {
+            files: dummy_tracks(&[
+                "music/1.mp3",
+                "music/2.mp3",
+                "music/3.mp3",
+                "music/4.mp3",
+                "music/5.mp3",
+            ]),
+            inputs: vec![InputSource::new(PathBuf::from("music"))],
+            ..InputFiles::default()
+        };
+        let arrangement = Arrangement::from_config(&input, &test_config(&["--tracks", "2"])).expect("should arrange");
+        let orderings = unique_orderings(arrangement.as_ref(), &input.files);
+        // Two of five tracks can be picked in 5 * 4 orders
+        assert_eq!(orderings, Some(20));
+        assert_eq!(check_permutations_count(30, orderings), 20);
+        assert_eq!(unique_orderings(None, &input.files), Some(120));
     }
 
     #[test]
     fn detects_consecutive_tracks_from_same_artist() {
         let tracks = dummy_tracks(&["Artist A - One.mp3", "Artist A - Two.mp3", "Artist B - One.mp3"]);
-        assert!(check_consecutive_tracks_from_same_artist(&tracks));
+        assert!(check_consecutive_tracks_from_same_artist(&tracks, &HashMap::new()));
     }
 
     #[test]
     fn allows_interleaved_tracks_from_same_artist() {
         let tracks = dummy_tracks(&["Artist A - One.mp3", "Artist B - One.mp3", "Artist A - Two.mp3"]);
-        assert!(!check_consecutive_tracks_from_same_artist(&tracks));
+        assert!(!check_consecutive_tracks_from_same_artist(&tracks, &HashMap::new()));
     }
 
     #[test]
@@ -229,10 +1267,11 @@ mod permutation_tests {
         ]);
         let mut orderings = HashSet::new();
 
-        get_unique_file_ordering(&mut tracks, &mut orderings).expect("expected a valid unique ordering");
+        get_unique_file_ordering(&mut tracks, None, &HashMap::new(), &mut orderings, &mut test_rng())
+            .expect("expected a valid unique ordering");
 
         let hash = utils::get_ordering_hash(&tracks);
-        assert!(!check_consecutive_tracks_from_same_artist(&tracks));
+        assert!(!check_consecutive_tracks_from_same_artist(&tracks, &HashMap::new()));
         assert_eq!(orderings.len(), 1);
         assert!(orderings.contains(&hash));
     }
@@ -243,7 +1282,8 @@ mod permutation_tests {
         let existing_hash = utils::get_ordering_hash(&tracks);
         let mut orderings = HashSet::from([existing_hash]);
 
-        get_unique_file_ordering(&mut tracks, &mut orderings).expect("expected an alternative ordering");
+        get_unique_file_ordering(&mut tracks, None, &HashMap::new(), &mut orderings, &mut test_rng())
+            .expect("expected an alternative ordering");
 
         let new_hash = utils::get_ordering_hash(&tracks);
         assert_ne!(new_hash, existing_hash);
@@ -257,9 +1297,99 @@ mod permutation_tests {
         let mut tracks = dummy_tracks(&["Artist A - One.mp3", "Artist A - Two.mp3", "Artist A - Three.mp3"]);
         let mut orderings = HashSet::new();
 
-        let error =
-            get_unique_file_ordering(&mut tracks, &mut orderings).expect_err("expected ordering generation to fail");
+        let error = get_unique_file_ordering(&mut tracks, None, &HashMap::new(), &mut orderings, &mut test_rng())
+            .expect_err("expected ordering generation to fail");
 
         assert!(error.to_string().contains("Failed to create an unique random order"));
     }
+
+    #[test]
+    fn same_seed_gives_same_ordering() {
+        let names = [
+            "Artist A - One.mp3",
+            "Artist B - One.mp3",
+            "Artist C - One.mp3",
+            "Artist D - One.mp3",
+        ];
+        let mut first = dummy_tracks(&names);
+        let mut second = dummy_tracks(&names);
+        get_unique_file_ordering(&mut first, None, &HashMap::new(), &mut HashSet::new(), &mut test_rng())
+            .expect("expected an ordering");
+        get_unique_file_ordering(&mut second, None, &HashMap::new(), &mut HashSet::new(), &mut test_rng())
+            .expect("expected an ordering");
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn draws_random_excerpt_start_inside_safe_window() {
+        let window = SafeWindow {
+            skip_intro: 20.0,
+            skip_outro: 30.0,
+        };
+        let mut rng = test_rng();
+        for _ in 0..100 {
+            let start = random_excerpt_start(180.0, 60.0, window, &mut rng);
+            assert!((20.0..=90.0).contains(&start), "start {start} outside safe window");
+            assert!((start * 1000.0 - (start * 1000.0).round()).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn falls_back_to_fixed_start_for_short_tracks() {
+        let window = SafeWindow {
+            skip_intro: 20.0,
+            skip_outro: 30.0,
+        };
+        let mut rng = test_rng();
+        assert!((random_excerpt_start(100.0, 60.0, window, &mut rng) - 20.0).abs() < f64::EPSILON);
+        assert!((random_excerpt_start(70.0, 60.0, window, &mut rng) - 10.0).abs() < f64::EPSILON);
+        assert!(random_excerpt_start(30.0, 60.0, window, &mut rng).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn accepts_permutations_that_fit_into_free_space() {
+        assert_eq!(free_space_shortfall(100, 5, 500), None);
+        assert_eq!(free_space_shortfall(100, 0, 0), None);
+    }
+
+    #[test]
+    fn suggests_smaller_permutation_count_on_shortfall() {
+        let message = free_space_shortfall(100, 5, 350).expect("should report shortfall");
+        assert!(message.contains("At most 3 permutations fit"));
+
+        let message = free_space_shortfall(100, 5, 50).expect("should report shortfall");
+        assert!(message.contains("Not even a single permutation fits"));
+        assert!(message.contains("--link or --playlist-only"));
+    }
+
+    #[test]
+    fn removes_staging_dir_when_not_committed() {
+        let root = tempfile::tempdir().expect("should create temp dir");
+        let output_path = root.path().join("FDO Impro 1");
+
+        let staging_dir = StagingDir::create(&output_path).expect("should create staging dir");
+        let staging_path = staging_dir.path().to_path_buf();
+        fs::write(staging_path.join("01 track.mp3"), b"audio").expect("should write file");
+        drop(staging_dir);
+
+        assert!(!staging_path.exists());
+        assert!(!output_path.exists());
+    }
+
+    #[test]
+    fn commit_replaces_existing_output_dir() {
+        let root = tempfile::tempdir().expect("should create temp dir");
+        let output_path = root.path().join("FDO Impro 1");
+        fs::create_dir_all(&output_path).expect("should create output dir");
+        fs::write(output_path.join("old.mp3"), b"old").expect("should write file");
+
+        let staging_dir = StagingDir::create(&output_path).expect("should create staging dir");
+        fs::write(staging_dir.path().join("new.mp3"), b"new").expect("should write file");
+        staging_dir.commit(&output_path).expect("should commit staging dir");
+
+        assert!(output_path.join("new.mp3").exists());
+        assert!(!output_path.join("old.mp3").exists());
+        let leftovers: Vec<_> = fs::read_dir(root.path()).expect("should read dir").collect();
+        assert_eq!(leftovers.len(), 1);
+    }
 }
@@ -0,0 +1,247 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::audio::{self, AudioBuffer, Silence};
+use crate::config::Config;
+use crate::tags;
+
+/// RMS level in dBFS that tracks are matched to in combined files.
+const MATCHED_RMS_DB: f64 = -20.0;
+
+/// Size of the canonical WAV file header in bytes.
+const WAV_HEADER_SIZE: u64 = 44;
+
+/// Maximum channel count for converted and combined files.
+const TARGET_MAX_CHANNELS: usize = 2;
+
+/// Uniform format that all tracks are converted to when converting or combining.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TargetFormat {
+    pub sample_rate: u32,
+    pub channels: usize,
+    pub bit_depth: u16,
+}
+
+impl TargetFormat {
+    /// Use the configured sample rate or the highest sample rate of the input files,
+    /// and the highest channel count limited to stereo.
+    pub fn for_files(files: &[PathBuf], config: &Config) -> Result<Self> {
+        let mut format = Self {
+            sample_rate: 0,
+            channels: 1,
+            bit_depth: config.bit_depth.bits(),
+        };
+        for file in files {
+            let properties = tags::read_properties(file)?;
+            format.sample_rate = format.sample_rate.max(properties.sample_rate().unwrap_or(44100));
+            format.channels = format
+                .channels
+                .max(properties.channels().map_or(2, usize::from))
+                .min(TARGET_MAX_CHANNELS);
+        }
+        if let Some(sample_rate) = config.sample_rate {
+            format.sample_rate = sample_rate;
+        }
+        Ok(format)
+    }
+
+    /// Size in bytes of the given duration.
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    pub fn size_of(&self, seconds: f64) -> u64 {
+        let bytes_per_second = f64::from(self.sample_rate) * self.channels as f64 * f64::from(self.bit_depth / 8);
+        (seconds * bytes_per_second).ceil() as u64
+    }
+}
+
+/// Processing for one planned track on top of the config.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TrackOptions {
+    /// Excerpt start position in seconds from a sidecar CUE file or drawn at random
+    pub excerpt_start: Option<f64>,
+    /// Loudness normalization gain in dB
+    pub gain_db: Option<f64>,
+    /// Leading and trailing silence to trim before cutting the excerpt
+    pub trim: Option<Silence>,
+}
+
+impl TrackOptions {
+    /// Short description for verbose output, for example `start 12.000s, gain -3.1 dB`.
+    pub fn describe(&self) -> Option<String> {
+        let mut parts = Vec::new();
+        if let Some(start) = self.excerpt_start {
+            parts.push(format!("start {start:.3}s"));
+        }
+        if let Some(gain) = self.gain_db {
+            parts.push(format!("gain {gain:+.1} dB"));
+        }
+        if let Some(trim) = self.trim.filter(|trim| trim.leading > 0.0 || trim.trailing > 0.0) {
+            parts.push(format!("trim {:.2}s/{:.2}s", trim.leading, trim.trailing));
+        }
+        (!parts.is_empty()).then(|| parts.join(", "))
+    }
+}
+
+/// Decode the input file and apply the configured processing.
+///
+/// Silence is trimmed first, so excerpt starts count from the end of the leading silence.
+pub fn render_track(source: &Path, options: TrackOptions, config: &Config) -> Result<AudioBuffer> {
+    let mut audio = audio::decode_file(source)?;
+    if let Some(silence) = options.trim {
+        audio = audio.trim(silence);
+    }
+    if let Some(gain) = options.gain_db {
+        audio.apply_gain(db_to_linear(gain));
+    }
+    if let Some(excerpt) = &config.excerpt {
+        let start = options.excerpt_start.unwrap_or(excerpt.start);
+        audio = audio
+            .excerpt(start, excerpt.length)
+            .context(format!("Failed to cut excerpt: {}", source.display()))?;
+        audio.apply_fades(excerpt.fade_in, excerpt.fade_out);
+    }
+    Ok(audio)
+}
+
+/// Render the input file to the output path in the configured output format.
+///
+/// The track is converted to the target format if given, and the tags of the source are copied over.
+/// Returns the size of the written file in bytes.
+pub fn render_file(
+    source: &Path,
+    output: &Path,
+    options: TrackOptions,
+    target: Option<TargetFormat>,
+    config: &Config,
+) -> Result<u64> {
+    let mut audio = render_track(source, options, config)?;
+    if let Some(format) = target {
+        audio = audio
+            .convert(format.sample_rate, format.channels)
+            .context(format!("Failed to convert audio: {}", source.display()))?;
+    }
+    audio::write_audio_file(output, &audio, config.output_format, config.bit_depth.bits())?;
+    tags::copy_tags(source, output)?;
+    Ok(std::fs::metadata(output)?.len())
+}
+
+/// Scale the track to the reference RMS level, limited so the peak does not clip.
+///
+/// Returns the applied gain in dB.
+pub fn match_gain(audio: &mut AudioBuffer) -> f64 {
+    let rms = audio.rms();
+    let peak = audio.peak();
+    if rms <= 0.0 || peak <= 0.0 {
+        return 0.0;
+    }
+    let gain = (db_to_linear(MATCHED_RMS_DB) / rms).min(1.0 / peak);
+    audio.apply_gain(gain);
+    20.0 * gain.log10()
+}
+
+fn db_to_linear(db: f64) -> f64 {
+    10.0_f64.powf(db / 20.0)
+}
+
+/// Estimated size of the rendered output file in bytes.
+///
+/// Uses the uncompressed size, which is an upper bound for FLAC output.
+pub fn estimated_size(source: &Path, target: Option<TargetFormat>, config: &Config) -> Result<u64> {
+    let properties = tags::read_properties(source)?;
+    let seconds = rendered_duration(properties.duration().as_secs_f64(), config);
+    let format = target.unwrap_or_else(|| TargetFormat {
+        sample_rate: properties.sample_rate().unwrap_or(48000),
+        channels: properties.channels().map_or(2, usize::from),
+        bit_depth: config.bit_depth.bits(),
+    });
+    Ok(format.size_of(seconds) + WAV_HEADER_SIZE)
+}
+
+/// Estimated size of a combined permutation file in bytes.
+pub fn estimated_combined_size(files: &[PathBuf], format: TargetFormat, config: &Config) -> Result<u64> {
+    let mut seconds = 0.0;
+    for file in files {
+        seconds += rendered_duration(tags::read_properties(file)?.duration().as_secs_f64(), config);
+    }
+    if let Some(combine) = &config.combine {
+        #[allow(clippy::cast_precision_loss)]
+        let gaps = files.len().saturating_sub(1) as f64;
+        seconds += combine.crossfade.map_or(combine.gap, |crossfade| -crossfade) * gaps;
+    }
+    Ok(format.size_of(seconds.max(0.0)) + WAV_HEADER_SIZE)
+}
+
+/// Length in seconds of a rendered track with the given input duration.
+fn rendered_duration(duration: f64, config: &Config) -> f64 {
+    config.excerpt.map_or(duration, |excerpt| duration.min(excerpt.length))
+}
+
+#[cfg(test)]
+mod render_tests {
+    use super::*;
+
+    #[test]
+    fn matches_gain_to_reference_level() {
+        let mut quiet = AudioBuffer {
+            sample_rate: 100,
+            channels: 1,
+            samples: vec![0.01, -0.01],
+        };
+        let gain = match_gain(&mut quiet);
+        assert!((gain - 20.0).abs() < 1e-4);
+        assert!((quiet.rms() - db_to_linear(MATCHED_RMS_DB)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn limits_gain_to_avoid_clipping() {
+        let mut spiky = AudioBuffer {
+            sample_rate: 100,
+            channels: 1,
+            samples: [vec![0.5], vec![0.0; 999]].concat(),
+        };
+        match_gain(&mut spiky);
+        assert!((spiky.peak() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn keeps_source_tags_when_converting() {
+        use clap::Parser;
+        use lofty::config::WriteOptions;
+        use lofty::file::{AudioFile, TaggedFileExt};
+        use lofty::tag::{Accessor, Tag, TagType};
+
+        let dir = tempfile::tempdir().expect("should create temp dir");
+        let source = dir.path().join("Artist - Song.wav");
+        let audio = AudioBuffer {
+            sample_rate: 44100,
+            channels: 2,
+            samples: vec![0.1; 2 * 4410],
+        };
+        audio::write_audio_file(&source, &audio, audio::AudioFormat::Wav, 16).expect("should write audio");
+        let mut tagged_file = lofty::read_from_path(&source).expect("should read file");
+        let mut tag = Tag::new(TagType::Id3v2);
+        tag.set_artist("Tagged Artist".to_string());
+        tag.set_title("Tagged Title".to_string());
+        tag.set_genre("Disco".to_string());
+        tagged_file.insert_tag(tag);
+        tagged_file
+            .save_to_path(&source, WriteOptions::default())
+            .expect("should save tags");
+
+        let args =
+            crate::Args::try_parse_from(["fdo-randomizer", "music", "--convert-to", "flac"]).expect("should parse");
+        let config = Config::from_args(&args, 1).expect("should create config");
+        let output = dir.path().join("01 FDO impro - Artist - Song.flac");
+        render_file(&source, &output, TrackOptions::default(), None, &config).expect("should render file");
+
+        let info = tags::read_file_info(&output).expect("should read file info");
+        assert_eq!(info.codec, "FLAC");
+        assert_eq!(info.artist.as_deref(), Some("Tagged Artist"));
+        assert_eq!(info.title.as_deref(), Some("Tagged Title"));
+        assert_eq!(info.genre.as_deref(), Some("Disco"));
+    }
+}
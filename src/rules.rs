@@ -0,0 +1,403 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use colored::Colorize;
+use rayon::prelude::*;
+use serde::Deserialize;
+
+use crate::filter::Filter;
+use crate::input::{self, InputSource};
+use crate::tags::{self, FileInfo};
+use crate::utils::format_duration;
+
+/// How a broken rule is handled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Refuse to generate permutations
+    #[default]
+    Error,
+    /// Print a warning and continue
+    #[serde(alias = "warning")]
+    Warn,
+}
+
+/// Competition category rules that every input file must follow.
+///
+/// Loaded from a TOML file with one table per rule, for example:
+///
+/// ```toml
+/// [duration]
+/// min = 90
+/// max = 240
+///
+/// [bitrate]
+/// min = 192
+/// severity = "warn"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rules {
+    /// Track length in seconds
+    pub duration: Option<RangeRule>,
+    /// Allowed codecs such as "flac", "wav" or "mp3"
+    pub format: Option<AllowedRule<String>>,
+    /// Allowed sample rates in Hz
+    pub sample_rate: Option<AllowedRule<u32>>,
+    /// Audio bitrate in kbps
+    pub bitrate: Option<RangeRule>,
+    /// Tags that must be present
+    pub tags: Option<TagsRule>,
+    /// Tempo range for files with a BPM tag
+    pub bpm: Option<RangeRule>,
+    /// Parental advisory flag for explicit lyrics
+    pub explicit: Option<ExplicitRule>,
+}
+
+/// Inclusive limits for a numeric value.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RangeRule {
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    #[serde(default)]
+    pub severity: Severity,
+}
+
+/// List of allowed values.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AllowedRule<T> {
+    pub allowed: Vec<T>,
+    #[serde(default)]
+    pub severity: Severity,
+}
+
+/// Tags that must be present and not empty.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TagsRule {
+    pub required: Vec<TagName>,
+    #[serde(default)]
+    pub severity: Severity,
+}
+
+/// Explicit lyrics are not allowed unless `allowed` is set.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExplicitRule {
+    #[serde(default)]
+    pub allowed: bool,
+    #[serde(default)]
+    pub severity: Severity,
+}
+
+/// Tag that a rule can require.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TagName {
+    Artist,
+    Title,
+    Album,
+    Genre,
+    Bpm,
+}
+
+/// One broken rule for one input file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub path: PathBuf,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for TagName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Artist => "artist",
+            Self::Title => "title",
+            Self::Album => "album",
+            Self::Genre => "genre",
+            Self::Bpm => "BPM",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl RangeRule {
+    /// Describe how the value breaks the limits, formatted with the given function.
+    fn check(&self, name: &str, value: f64, format: impl Fn(f64) -> String) -> Option<String> {
+        if let Some(min) = self.min
+            && value < min
+        {
+            return Some(format!("{name} {} is below the minimum {}", format(value), format(min)));
+        }
+        if let Some(max) = self.max
+            && value > max
+        {
+            return Some(format!("{name} {} is above the maximum {}", format(value), format(max)));
+        }
+        None
+    }
+}
+
+impl Rules {
+    /// Load rules from a TOML file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path).context(format!("Failed to read rules file: {}", path.display()))?;
+        toml::from_str(&contents).context(format!("Invalid rules file: {}", path.display()))
+    }
+
+    /// Check the file against all rules and return the broken ones with their severity.
+    pub fn check(&self, info: &FileInfo) -> Vec<(Severity, String)> {
+        let mut broken = Vec::new();
+        let properties = &info.properties;
+
+        if let Some(rule) = &self.duration
+            && let Some(message) = rule.check("duration", properties.duration().as_secs_f64(), format_duration)
+        {
+            broken.push((rule.severity, message));
+        }
+        if let Some(rule) = &self.format
+            && !rule.allowed.iter().any(|codec| codec.eq_ignore_ascii_case(&info.codec))
+        {
+            broken.push((rule.severity, format!("format {} is not allowed", info.codec)));
+        }
+        if let Some(rule) = &self.sample_rate
+            && let Some(sample_rate) = properties.sample_rate()
+            && !rule.allowed.contains(&sample_rate)
+        {
+            broken.push((rule.severity, format!("sample rate {sample_rate} Hz is not allowed")));
+        }
+        if let Some(rule) = &self.bitrate
+            && let Some(bitrate) = properties.audio_bitrate()
+            && let Some(message) = rule.check("bitrate", f64::from(bitrate), |kbps| format!("{kbps} kbps"))
+        {
+            broken.push((rule.severity, message));
+        }
+        if let Some(rule) = &self.tags {
+            for tag in rule.required.iter().filter(|&&tag| !has_tag(info, tag)) {
+                broken.push((rule.severity, format!("missing {tag} tag")));
+            }
+        }
+        if let Some(rule) = &self.bpm
+            && let Some(bpm) = info.bpm
+            && let Some(message) = rule.check("BPM", bpm, |bpm| format!("{bpm}"))
+        {
+            broken.push((rule.severity, message));
+        }
+        if let Some(rule) = &self.explicit
+            && info.explicit
+            && !rule.allowed
+        {
+            broken.push((rule.severity, "explicit lyrics are not allowed".to_string()));
+        }
+        broken
+    }
+}
+
+/// Check every file against the rules.
+///
+/// Files that cannot be read are reported as errors.
+pub fn validate_files(files: &[PathBuf], rules: &Rules) -> Vec<Violation> {
+    files
+        .par_iter()
+        .flat_map_iter(|file| {
+            let broken = match tags::read_file_info(file) {
+                Ok(info) => rules.check(&info),
+                Err(error) => vec![(Severity::Error, format!("{error:#}"))],
+            };
+            broken.into_iter().map(|(severity, message)| Violation {
+                path: file.clone(),
+                severity,
+                message,
+            })
+        })
+        .collect()
+}
+
+/// Validate the files and print the broken rules.
+///
+/// Returns the number of files that only break rules with warning severity.
+///
+/// # Errors
+/// Returns an error if any file breaks a rule with error severity.
+pub fn enforce(files: &[PathBuf], rules: &Rules, verbose: bool) -> Result<usize> {
+    let violations = validate_files(files, rules);
+    for violation in &violations {
+        let name = violation.path.file_name().unwrap_or_default().to_string_lossy();
+        match violation.severity {
+            Severity::Error => eprintln!("{}", format!("Error: {name}: {}", violation.message).red()),
+            Severity::Warn => eprintln!("{}", format!("Warning: {name}: {}", violation.message).yellow()),
+        }
+    }
+
+    let errors = violations
+        .iter()
+        .filter(|violation| violation.severity == Severity::Error)
+        .count();
+    if errors > 0 {
+        anyhow::bail!("{errors} rule violation(s) with error severity, refusing to generate");
+    }
+    if verbose && violations.is_empty() {
+        println!("All {} files pass the rules\n", files.len());
+    } else if !violations.is_empty() {
+        eprintln!();
+    }
+    let warned: HashSet<&PathBuf> = violations.iter().map(|violation| &violation.path).collect();
+    Ok(warned.len())
+}
+
+/// Validate the audio files in the input directory against the rules file.
+pub fn validate(input_path: &Path, rules_path: &Path, recursive: bool) -> Result<()> {
+    let rules = Rules::load(rules_path)?;
+    let files = input::gather_inputs(
+        &[InputSource::new(input_path.to_path_buf())],
+        recursive,
+        &Filter::default(),
+    )?
+    .files;
+    if files.is_empty() {
+        anyhow::bail!("No audio files found in: '{}'", input_path.display());
+    }
+    let warned = enforce(&files, &rules, false)?;
+    if warned > 0 {
+        println!(
+            "{}",
+            format!("{} files pass the rules, {warned} of them with warnings", files.len()).yellow()
+        );
+    } else {
+        println!("{}", format!("{} files pass the rules", files.len()).green());
+    }
+    Ok(())
+}
+
+fn has_tag(info: &FileInfo, tag: TagName) -> bool {
+    let value = match tag {
+        TagName::Artist => info.artist.as_deref(),
+        TagName::Title => info.title.as_deref(),
+        TagName::Album => info.album.as_deref(),
+        TagName::Genre => info.genre.as_deref(),
+        TagName::Bpm => return info.bpm.is_some(),
+    };
+    value.is_some_and(|value| !value.is_empty())
+}
+
+#[cfg(test)]
+mod rules_tests {
+    use std::time::Duration;
+
+    use lofty::properties::FileProperties;
+
+    use super::*;
+
+    fn file_info(duration: f64, codec: &str, bitrate: u32) -> FileInfo {
+        FileInfo {
+            codec: codec.to_string(),
+            properties: FileProperties::new(
+                Duration::from_secs_f64(duration),
+                Some(bitrate),
+                Some(bitrate),
+                Some(44100),
+                None,
+                Some(2),
+                None,
+            ),
+            artist: Some("Artist".to_string()),
+            title: Some("Song".to_string()),
+            album: None,
+            genre: None,
+            bpm: Some(150.0),
+            explicit: true,
+        }
+    }
+
+    const RULES: &str = r#"
+[duration]
+min = 90
+max = 240
+
+[format]
+allowed = ["flac", "wav", "mp3"]
+
+[bitrate]
+min = 192
+severity = "warn"
+
+[tags]
+required = ["artist", "genre"]
+severity = "warning"
+
+[bpm]
+max = 140
+
+[explicit]
+"#;
+
+    #[test]
+    fn parses_rules_with_default_severity() {
+        let rules: Rules = toml::from_str(RULES).expect("should parse rules");
+        assert_eq!(rules.duration.map(|rule| rule.severity), Some(Severity::Error));
+        assert_eq!(rules.bitrate.map(|rule| rule.severity), Some(Severity::Warn));
+        assert_eq!(rules.tags.map(|rule| rule.severity), Some(Severity::Warn));
+        assert_eq!(rules.explicit, Some(ExplicitRule::default()));
+        assert!(rules.sample_rate.is_none());
+    }
+
+    #[test]
+    fn rejects_unknown_rules() {
+        assert!(toml::from_str::<Rules>("[loudness]\nmax = -14\n").is_err());
+    }
+
+    #[test]
+    fn reports_broken_rules() {
+        let rules: Rules = toml::from_str(RULES).expect("should parse rules");
+        // The regular track only breaks the genre, BPM and explicit rules
+        assert_eq!(rules.check(&file_info(180.0, "FLAC", 900)).len(), 3);
+
+        let broken = rules.check(&file_info(75.0, "AAC", 128));
+        assert_eq!(
+            broken,
+            [
+                (Severity::Error, "duration 1:15 is below the minimum 1:30".to_string()),
+                (Severity::Error, "format AAC is not allowed".to_string()),
+                (
+                    Severity::Warn,
+                    "bitrate 128 kbps is below the minimum 192 kbps".to_string()
+                ),
+                (Severity::Warn, "missing genre tag".to_string()),
+                (Severity::Error, "BPM 150 is above the maximum 140".to_string()),
+                (Severity::Error, "explicit lyrics are not allowed".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn counts_files_with_warnings_only() {
+        use crate::audio::{self, AudioBuffer, AudioFormat};
+
+        let dir = tempfile::tempdir().expect("should create temp dir");
+        let write_wav = |name: &str, seconds: usize| {
+            let path = dir.path().join(name);
+            let audio = AudioBuffer {
+                sample_rate: 8000,
+                channels: 1,
+                samples: vec![0.1; seconds * 8000],
+            };
+            audio::write_audio_file(&path, &audio, AudioFormat::Wav, 16).expect("should write audio");
+            path
+        };
+        let files = vec![write_wav("Artist A - One.wav", 2), write_wav("Artist B - Two.wav", 3)];
+        let rules: Rules =
+            toml::from_str("[duration]\nmin = 1\n\n[tags]\nrequired = [\"genre\"]\nseverity = \"warn\"\n")
+                .expect("should parse rules");
+
+        assert_eq!(enforce(&files, &rules, false).expect("should only warn"), 2);
+
+        let short = write_wav("Artist C - Three.wav", 0);
+        assert!(enforce(&[short], &rules, false).is_err());
+    }
+}
@@ -0,0 +1,447 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use lofty::config::WriteOptions;
+use lofty::file::{AudioFile, FileType, TaggedFile, TaggedFileExt};
+use lofty::probe::Probe;
+use lofty::properties::FileProperties;
+use lofty::tag::{Accessor, ItemKey, Tag, TagType};
+
+/// Codec, audio properties and artist and title tags of one file.
+#[derive(Debug, Clone)]
+pub struct FileInfo {
+    /// Codec name such as FLAC or MP3
+    pub codec: String,
+    pub properties: FileProperties,
+    pub artist: Option<String>,
+    pub title: Option<String>,
+    pub album: Option<String>,
+    pub genre: Option<String>,
+    /// Tempo from the BPM tag
+    pub bpm: Option<f64>,
+    /// Parental advisory tag marks explicit lyrics
+    pub explicit: bool,
+}
+
+/// Playlist position tags for one output file.
+#[derive(Debug, Clone)]
+pub struct PlaylistTags<'a> {
+    /// Slot number in the permutation
+    pub track: usize,
+    /// Number of tracks in the permutation
+    pub track_total: usize,
+    /// Permutation output name
+    pub album: &'a str,
+    /// Event name
+    pub album_artist: &'a str,
+}
+
+/// Write track number, album and album artist tags into the given output file.
+///
+/// Creates the primary tag type for the file format if the file has no tags yet.
+/// Secondary tags such as RIFF INFO in WAV or `ID3v1` in MP3 files are updated too,
+/// so players that read them do not show the album and track of the source file.
+/// Other existing tag values such as artist and title are kept as is.
+pub fn write_playlist_tags(path: &Path, tags: &PlaylistTags) -> Result<()> {
+    let mut tagged_file = read_tagged_file(path)?;
+    primary_tag_mut(&mut tagged_file, path)?;
+
+    let tag_types: Vec<TagType> = tagged_file.tags().iter().map(Tag::tag_type).collect();
+    for tag_type in tag_types {
+        let Some(tag) = tagged_file.tag_mut(tag_type) else {
+            continue;
+        };
+        #[allow(clippy::cast_possible_truncation)]
+        {
+            tag.set_track(tags.track as u32);
+            tag.set_track_total(tags.track_total as u32);
+        }
+        tag.set_album(tags.album.to_string());
+        tag.insert_text(ItemKey::AlbumArtist, tags.album_artist.to_string());
+    }
+
+    tagged_file
+        .save_to_path(path, WriteOptions::default())
+        .context(format!("Failed to write tags: {}", path.display()))
+}
+
+/// Write the comment tag into the given output file, replacing an existing comment.
+pub fn write_comment(path: &Path, comment: &str) -> Result<()> {
+    let mut tagged_file = read_tagged_file(path)?;
+    primary_tag_mut(&mut tagged_file, path)?.set_comment(comment.to_string());
+    tagged_file
+        .save_to_path(path, WriteOptions::default())
+        .context(format!("Failed to write tags: {}", path.display()))
+}
+
+/// Copy the tag items and pictures of the source file into the rendered output file.
+///
+/// Rendered files are written from the decoded samples,
+/// so without this they would lose the artist, title, genre and other tags of the source.
+/// Items the tag type of the output format cannot hold are left out.
+pub fn copy_tags(source: &Path, output: &Path) -> Result<()> {
+    let source_file = read_tagged_file(source)?;
+    let Some(source_tag) = source_file.primary_tag().or_else(|| source_file.first_tag()) else {
+        return Ok(());
+    };
+    let mut tagged_file = read_tagged_file(output)?;
+    let tag = primary_tag_mut(&mut tagged_file, output)?;
+    for item in source_tag.items() {
+        tag.insert(item.clone());
+    }
+    for picture in source_tag.pictures() {
+        tag.push_picture(picture.clone());
+    }
+    tagged_file
+        .save_to_path(output, WriteOptions::default())
+        .context(format!("Failed to write tags: {}", output.display()))
+}
+
+/// Read the artist and title tags of the given file.
+pub fn read_artist_and_title(path: &Path) -> Result<(Option<String>, Option<String>)> {
+    let tagged_file = read_tagged_file(path)?;
+    let tag = tagged_file.primary_tag().or_else(|| tagged_file.first_tag());
+    Ok((
+        tag.and_then(Accessor::artist).map(|artist| artist.trim().to_string()),
+        tag.and_then(Accessor::title).map(|title| title.trim().to_string()),
+    ))
+}
+
+/// Read the codec, audio properties and artist and title tags of the given file.
+pub fn read_file_info(path: &Path) -> Result<FileInfo> {
+    let tagged_file = read_tagged_file(path)?;
+    let properties = tagged_file.properties().clone();
+    let codec = match tagged_file.file_type() {
+        FileType::Aiff => "AIFF".to_string(),
+        FileType::Flac => "FLAC".to_string(),
+        FileType::Mpeg => "MP3".to_string(),
+        // ALAC is the only MP4 audio codec that reports a bit depth
+        FileType::Mp4 if properties.bit_depth().is_some() => "ALAC".to_string(),
+        FileType::Mp4 | FileType::Aac => "AAC".to_string(),
+        FileType::Wav => "WAV".to_string(),
+        other => format!("{other:?}").to_uppercase(),
+    };
+    let tag = tagged_file.primary_tag().or_else(|| tagged_file.first_tag());
+    Ok(FileInfo {
+        codec,
+        artist: tag.and_then(Accessor::artist).map(|artist| artist.trim().to_string()),
+        title: tag.and_then(Accessor::title).map(|title| title.trim().to_string()),
+        album: tag.and_then(Accessor::album).map(|album| album.trim().to_string()),
+        genre: tag.and_then(Accessor::genre).map(|genre| genre.trim().to_string()),
+        bpm: tag.and_then(read_bpm),
+        explicit: tag.is_some_and(is_explicit),
+        properties,
+    })
+}
+
+/// Read the tempo from the decimal or integer BPM tag.
+fn read_bpm(tag: &Tag) -> Option<f64> {
+    tag.get_string(ItemKey::Bpm)
+        .or_else(|| tag.get_string(ItemKey::IntegerBpm))
+        .and_then(|bpm| bpm.trim().parse().ok())
+        .filter(|bpm: &f64| *bpm > 0.0)
+}
+
+/// Returns true if the parental advisory tag marks the track explicit.
+///
+/// iTunes uses 1 and 4 for explicit, 2 for clean and 0 for no rating.
+fn is_explicit(tag: &Tag) -> bool {
+    tag.get_string(ItemKey::ParentalAdvisory).is_some_and(|rating| {
+        let rating = rating.trim();
+        rating == "1" || rating == "4" || rating.eq_ignore_ascii_case("explicit")
+    })
+}
+
+/// Read audio properties such as duration and sample rate from the file headers.
+pub fn read_properties(path: &Path) -> Result<FileProperties> {
+    Ok(read_tagged_file(path)?.properties().clone())
+}
+
+/// Return the primary tag, creating it first if the file has no tags yet.
+fn primary_tag_mut<'a>(tagged_file: &'a mut TaggedFile, path: &Path) -> Result<&'a mut Tag> {
+    if tagged_file.primary_tag().is_none() {
+        let tag_type = tagged_file.primary_tag_type();
+        tagged_file.insert_tag(Tag::new(tag_type));
+    }
+    tagged_file
+        .primary_tag_mut()
+        .context(format!("Failed to create tag: {}", path.display()))
+}
+
+/// Read the file, detecting the file type from the content instead of the extension.
+fn read_tagged_file(path: &Path) -> Result<TaggedFile> {
+    let probe = Probe::open(path).context(format!("Failed to open file: {}", path.display()))?;
+    let probe = probe
+        .guess_file_type()
+        .context(format!("Failed to detect file type: {}", path.display()))?;
+    probe.read().context(format!("Failed to read tags: {}", path.display()))
+}
+
+#[cfg(test)]
+mod tags_tests {
+    use super::*;
+
+    fn write_test_wav(path: &Path) {
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 44100,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(path, spec).expect("should create wav");
+        for _ in 0..44100 {
+            writer.write_sample(0i16).expect("should write sample");
+        }
+        writer.finalize().expect("should finalize wav");
+    }
+
+    fn write_test_flac(path: &Path) {
+        let audio = crate::audio::AudioBuffer {
+            sample_rate: 44100,
+            channels: 2,
+            samples: vec![0.0; 2 * 44100],
+        };
+        crate::audio::write_audio_file(path, &audio, crate::audio::AudioFormat::Flac, 16).expect("should write flac");
+    }
+
+    /// Write silent MPEG-1 Layer III frames at 128 kbit/s and 44.1 kHz.
+    fn write_test_mp3(path: &Path) {
+        let mut frame = vec![0xFF, 0xFB, 0x90, 0x64];
+        frame.resize(417, 0);
+        std::fs::write(path, frame.repeat(40)).expect("should write mp3");
+    }
+
+    /// MP4 atom with a big-endian length that includes the eight byte header.
+    fn mp4_atom(kind: [u8; 4], contents: &[u8]) -> Vec<u8> {
+        let mut atom = u32::try_from(contents.len() + 8)
+            .expect("should fit")
+            .to_be_bytes()
+            .to_vec();
+        atom.extend(kind);
+        atom.extend(contents);
+        atom
+    }
+
+    /// AIFF chunk with a big-endian length of the contents.
+    fn aiff_chunk(id: [u8; 4], contents: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend(u32::try_from(contents.len()).expect("should fit").to_be_bytes());
+        chunk.extend(contents);
+        chunk
+    }
+
+    /// Write a minimal M4A file with one second of audio track and no sample table.
+    fn write_test_m4a(path: &Path) {
+        let ftyp = mp4_atom(*b"ftyp", b"M4A \0\0\0\0M4A isom");
+        // Version, flags, creation and modification time, then time scale and duration
+        let mut mdhd = vec![0; 12];
+        mdhd.extend(44100u32.to_be_bytes());
+        mdhd.extend(44100u32.to_be_bytes());
+        mdhd.extend([0; 4]);
+        let mut hdlr = vec![0; 8];
+        hdlr.extend(b"soun");
+        hdlr.extend([0; 13]);
+        let mdia = mp4_atom(
+            *b"mdia",
+            &[mp4_atom(*b"mdhd", &mdhd), mp4_atom(*b"hdlr", &hdlr)].concat(),
+        );
+        let moov = mp4_atom(*b"moov", &mp4_atom(*b"trak", &mdia));
+        let mdat = mp4_atom(*b"mdat", &[0; 1024]);
+        std::fs::write(path, [ftyp, moov, mdat].concat()).expect("should write m4a");
+    }
+
+    /// Write one second of silent 16-bit stereo AIFF audio at 44.1 kHz.
+    fn write_test_aiff(path: &Path) {
+        let mut comm = 2u16.to_be_bytes().to_vec();
+        comm.extend(44100u32.to_be_bytes());
+        comm.extend(16u16.to_be_bytes());
+        // 44100 as an 80-bit extended precision number
+        comm.extend([0x40, 0x0E, 0xAC, 0x44, 0, 0, 0, 0, 0, 0]);
+        // Offset and block size, then the samples
+        let ssnd = vec![0; 8 + 44100 * 4];
+        let chunks = [
+            b"AIFF".to_vec(),
+            aiff_chunk(*b"COMM", &comm),
+            aiff_chunk(*b"SSND", &ssnd),
+        ]
+        .concat();
+        std::fs::write(path, aiff_chunk(*b"FORM", &chunks)).expect("should write aiff");
+    }
+
+    fn write_artist(path: &Path) {
+        let mut tagged_file = read_tagged_file(path).expect("should read file");
+        primary_tag_mut(&mut tagged_file, path)
+            .expect("should create tag")
+            .set_artist("Artist".to_string());
+        tagged_file
+            .save_to_path(path, WriteOptions::default())
+            .expect("should save tags");
+    }
+
+    /// Write the playlist tags twice and check that the second values replace the first.
+    fn assert_playlist_tags_round_trip(path: &Path, tag_type: TagType) {
+        for (track, album) in [(1, "FDO Impro 01"), (4, "FDO Impro 02")] {
+            let tags = PlaylistTags {
+                track,
+                track_total: 9,
+                album,
+                album_artist: "FDO Impro",
+            };
+            write_playlist_tags(path, &tags).expect("should write tags");
+        }
+
+        let tagged_file = lofty::read_from_path(path).expect("should read file");
+        let tag = tagged_file.tag(tag_type).expect("should have primary tag");
+        assert_eq!(tag.track(), Some(4));
+        assert_eq!(tag.track_total(), Some(9));
+        assert_eq!(tag.album().as_deref(), Some("FDO Impro 02"));
+        assert_eq!(tag.get_string(ItemKey::AlbumArtist), Some("FDO Impro"));
+        assert_eq!(tag.artist().as_deref(), Some("Artist"));
+    }
+
+    #[test]
+    fn writes_playlist_tags_to_flac() {
+        let dir = tempfile::tempdir().expect("should create temp dir");
+        let path = dir.path().join("01 FDO impro - Artist - Song.flac");
+        write_test_flac(&path);
+        write_artist(&path);
+
+        assert_playlist_tags_round_trip(&path, TagType::VorbisComments);
+    }
+
+    #[test]
+    fn writes_playlist_tags_to_mp3() {
+        let dir = tempfile::tempdir().expect("should create temp dir");
+        let path = dir.path().join("01 FDO impro - Artist - Song.mp3");
+        write_test_mp3(&path);
+        write_artist(&path);
+
+        assert_playlist_tags_round_trip(&path, TagType::Id3v2);
+        assert_eq!(
+            read_properties(&path).expect("should read properties").audio_bitrate(),
+            Some(128)
+        );
+    }
+
+    #[test]
+    fn writes_playlist_tags_to_m4a() {
+        let dir = tempfile::tempdir().expect("should create temp dir");
+        let path = dir.path().join("01 FDO impro - Artist - Song.m4a");
+        write_test_m4a(&path);
+        write_artist(&path);
+
+        assert_playlist_tags_round_trip(&path, TagType::Mp4Ilst);
+        assert_eq!(read_file_info(&path).expect("should read file info").codec, "AAC");
+    }
+
+    #[test]
+    fn writes_playlist_tags_to_aiff() {
+        let dir = tempfile::tempdir().expect("should create temp dir");
+        let path = dir.path().join("01 FDO impro - Artist - Song.aiff");
+        write_test_aiff(&path);
+        write_artist(&path);
+
+        assert_playlist_tags_round_trip(&path, TagType::Id3v2);
+        assert_eq!(read_file_info(&path).expect("should read file info").codec, "AIFF");
+    }
+
+    #[test]
+    fn updates_album_and_track_in_secondary_tags() {
+        let dir = tempfile::tempdir().expect("should create temp dir");
+        let wav = dir.path().join("01 FDO impro - Artist - Song.wav");
+        write_test_wav(&wav);
+        let mp3 = dir.path().join("01 FDO impro - Artist - Song.mp3");
+        write_test_mp3(&mp3);
+
+        for (path, tag_type) in [(&wav, TagType::RiffInfo), (&mp3, TagType::Id3v1)] {
+            let mut tagged_file = read_tagged_file(path).expect("should read file");
+            let mut tag = Tag::new(tag_type);
+            tag.set_album("Source Album".to_string());
+            tag.set_track(7);
+            tagged_file.insert_tag(tag);
+            tagged_file
+                .save_to_path(path, WriteOptions::default())
+                .expect("should save tags");
+
+            let tags = PlaylistTags {
+                track: 2,
+                track_total: 9,
+                album: "FDO Impro 03",
+                album_artist: "FDO Impro",
+            };
+            write_playlist_tags(path, &tags).expect("should write tags");
+
+            let tagged_file = lofty::read_from_path(path).expect("should read file");
+            let tag = tagged_file.tag(tag_type).expect("should keep secondary tag");
+            assert_eq!(tag.album().as_deref(), Some("FDO Impro 03"));
+            assert_eq!(tag.track(), Some(2));
+            assert!(tagged_file.tag(TagType::Id3v2).is_some());
+        }
+    }
+
+    #[test]
+    fn writes_playlist_tags_to_untagged_wav() {
+        let dir = tempfile::tempdir().expect("should create temp dir");
+        let path = dir.path().join("01 FDO Impro - Artist - Song.wav");
+        write_test_wav(&path);
+
+        let tags = PlaylistTags {
+            track: 3,
+            track_total: 12,
+            album: "FDO Impro 05",
+            album_artist: "FDO Impro",
+        };
+        write_playlist_tags(&path, &tags).expect("should write tags");
+
+        let tagged_file = lofty::read_from_path(&path).expect("should read file");
+        let tag = tagged_file.tag(TagType::Id3v2).expect("should have ID3v2 tag");
+        assert_eq!(tag.track(), Some(3));
+        assert_eq!(tag.track_total(), Some(12));
+        assert_eq!(tag.album().as_deref(), Some("FDO Impro 05"));
+        assert_eq!(tag.get_string(ItemKey::AlbumArtist), Some("FDO Impro"));
+    }
+
+    #[test]
+    fn writes_comment_next_to_playlist_tags() {
+        let dir = tempfile::tempdir().expect("should create temp dir");
+        let path = dir.path().join("01 FDO Impro - Artist - Song.wav");
+        write_test_wav(&path);
+
+        write_comment(&path, "Excerpt start 42.125s, seed 7").expect("should write comment");
+        let tags = PlaylistTags {
+            track: 1,
+            track_total: 2,
+            album: "FDO Impro 1",
+            album_artist: "FDO Impro",
+        };
+        write_playlist_tags(&path, &tags).expect("should write tags");
+
+        let tagged_file = lofty::read_from_path(&path).expect("should read file");
+        let tag = tagged_file.tag(TagType::Id3v2).expect("should have ID3v2 tag");
+        assert_eq!(tag.comment().as_deref(), Some("Excerpt start 42.125s, seed 7"));
+        assert_eq!(tag.track(), Some(1));
+    }
+
+    #[test]
+    fn reads_bpm_and_explicit_flag() {
+        let dir = tempfile::tempdir().expect("should create temp dir");
+        let path = dir.path().join("Artist - Song.wav");
+        write_test_wav(&path);
+
+        let mut tagged_file = read_tagged_file(&path).expect("should read file");
+        let tag = primary_tag_mut(&mut tagged_file, &path).expect("should create tag");
+        tag.set_genre("Disco".to_string());
+        tag.insert_text(ItemKey::IntegerBpm, "124".to_string());
+        tag.insert_text(ItemKey::ParentalAdvisory, "1".to_string());
+        tagged_file
+            .save_to_path(&path, WriteOptions::default())
+            .expect("should save tags");
+
+        let info = read_file_info(&path).expect("should read file info");
+        assert_eq!(info.codec, "WAV");
+        assert_eq!(info.genre.as_deref(), Some("Disco"));
+        assert_eq!(info.bpm, Some(124.0));
+        assert!(info.explicit);
+        assert!(info.artist.is_none());
+    }
+}
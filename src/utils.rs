@@ -1,6 +1,7 @@
 use std::env;
 use std::hash::{DefaultHasher, Hash, Hasher};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 
 use anyhow::{Context, Result};
@@ -8,8 +9,32 @@ use clap::Command;
 use clap_complete::Shell;
 use colored::Colorize;
 
+use crate::playlist;
+
 static AUDIO_EXTENSIONS: [&str; 6] = ["aif", "aiff", "flac", "mp3", "m4a", "wav"];
 
+/// Set when the user has pressed Ctrl-C.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Install a Ctrl-C handler that requests the current operation to stop.
+///
+/// The first Ctrl-C only sets a flag so the running copy can stop and clean up after itself.
+/// A second Ctrl-C exits immediately.
+pub fn install_interrupt_handler() -> Result<()> {
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(130);
+        }
+        eprintln!("\n{}", "Interrupted, cleaning up...".yellow());
+    })
+    .context("Failed to set Ctrl-C handler")
+}
+
+/// Returns true if the user has requested to stop with Ctrl-C.
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
 /// Calculate hash for the given list order.
 pub fn get_ordering_hash(files: &Vec<PathBuf>) -> u64 {
     let mut hasher = DefaultHasher::new();
@@ -17,6 +42,35 @@ pub fn get_ordering_hash(files: &Vec<PathBuf>) -> u64 {
     hasher.finish()
 }
 
+/// Number of ordered picks of `k` out of `n` items, or `None` if it does not fit in `usize`.
+pub fn k_permutations(n: usize, k: usize) -> Option<usize> {
+    if k > n {
+        return Some(0);
+    }
+    (n - k + 1..=n).try_fold(1, usize::checked_mul)
+}
+
+/// Number of unordered picks of `k` out of `n` items, or `None` if it does not fit in `usize`.
+pub fn binomial(n: usize, k: usize) -> Option<usize> {
+    if k > n {
+        return Some(0);
+    }
+    // Each step gives the exact binomial coefficient of the next smaller pick
+    (0..k.min(n - k)).try_fold(1, |count: usize, index| {
+        Some(count.checked_mul(n - index)? / (index + 1))
+    })
+}
+
+/// Split a file stem in the format `<artist> - <title>` into artist and title.
+///
+/// Returns `None` for the artist if the name does not contain the separator.
+pub fn parse_artist_and_title(stem: &str) -> (Option<&str>, &str) {
+    match stem.split_once(" - ") {
+        Some((artist, title)) => (Some(artist.trim()), title.trim()),
+        None => (None, stem.trim()),
+    }
+}
+
 /// Returns true if the given file is one of the supported audio file types.
 pub fn is_audio_file(path: &Path) -> bool {
     path.extension().is_some_and(|ext| {
@@ -25,22 +79,72 @@ pub fn is_audio_file(path: &Path) -> bool {
     })
 }
 
+/// Make sure the owner can write to the given file.
+///
+/// Copies keep the permissions of the original, so a read-only input file would produce a read-only copy.
+pub fn ensure_writable(path: &Path) -> Result<()> {
+    let mut permissions = std::fs::metadata(path)
+        .context(format!("Failed to read file metadata: {}", path.display()))?
+        .permissions();
+    if !permissions.readonly() {
+        return Ok(());
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        permissions.set_mode(permissions.mode() | 0o200);
+    }
+    #[cfg(not(unix))]
+    #[allow(clippy::permissions_set_readonly_false)]
+    permissions.set_readonly(false);
+    std::fs::set_permissions(path, permissions).context(format!("Failed to make file writable: {}", path.display()))
+}
+
+/// Format a byte count as a human-readable size string.
+#[allow(clippy::cast_precision_loss)]
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "kB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1000.0 && unit < UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.2} {}", UNITS[unit])
+    }
+}
+
+/// Format seconds as `m:ss`, or `h:mm:ss` for an hour or more.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub fn format_duration(seconds: f64) -> String {
+    let total = seconds.round() as u64;
+    let (hours, minutes, seconds) = (total / 3600, total / 60 % 60, total % 60);
+    if hours > 0 {
+        format!("{hours}:{minutes:02}:{seconds:02}")
+    } else {
+        format!("{minutes}:{seconds:02}")
+    }
+}
+
 /// Pretty-print elapsed time duration.
 pub fn print_duration(elapsed: Duration) {
     let formatted_time = format!("{:.2}s", elapsed.as_secs_f64());
     println!("{}", format!("Finished in {formatted_time}").green());
 }
 
-/// Resolve the input directory to an absolute path.
+/// Resolve the input directory or playlist to an absolute path.
 ///
 /// If `input_path` is `None` or empty, the current working directory is used.
-/// The resolved path must exist and be a directory.
+/// The resolved path must exist and be a directory or an M3U, M3U8 or PLS playlist file.
 ///
 /// # Errors
 /// Returns an error if:
 /// - The current working directory cannot be determined
 /// - The input path does not exist or is not accessible
-/// - The input path is not a directory
+/// - The input path is not a directory or playlist
 /// - Path canonicalization fails
 pub fn resolve_input_path(input_path: Option<&Path>) -> Result<PathBuf> {
     let input_path = match input_path {
@@ -54,8 +158,9 @@ pub fn resolve_input_path(input_path: Option<&Path>) -> Result<PathBuf> {
             input_path.display()
         );
     }
-    if !input_path.is_dir() {
-        anyhow::bail!("Input path is not a directory: '{}'", input_path.display());
+    let is_playlist = input_path.is_file() && playlist::is_playlist(&input_path);
+    if !input_path.is_dir() && !is_playlist {
+        anyhow::bail!("Input path is not a directory or playlist: '{}'", input_path.display());
     }
 
     let absolute_input_path = dunce::canonicalize(&input_path)?;
@@ -174,3 +279,27 @@ pub fn generate_shell_completion(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod utils_tests {
+    use super::*;
+
+    #[test]
+    fn counts_picks() {
+        assert_eq!(k_permutations(5, 2), Some(20));
+        assert_eq!(k_permutations(3, 3), Some(6));
+        assert_eq!(k_permutations(2, 3), Some(0));
+        assert_eq!(k_permutations(21, 21), None);
+        assert_eq!(binomial(5, 2), Some(10));
+        assert_eq!(binomial(52, 5), Some(2_598_960));
+        assert_eq!(binomial(4, 0), Some(1));
+        assert_eq!(binomial(2, 3), Some(0));
+    }
+
+    #[test]
+    fn formats_durations() {
+        assert_eq!(format_duration(59.6), "1:00");
+        assert_eq!(format_duration(754.0), "12:34");
+        assert_eq!(format_duration(3725.0), "1:02:05");
+    }
+}